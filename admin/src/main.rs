@@ -0,0 +1,234 @@
+use std::env;
+use std::error::Error;
+use std::process::ExitCode;
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use shared::admin::{
+    unix_timestamp, AdminRequest, AdminResponse, RelayStats, TunnelInfo, DEFAULT_ADMIN_SOCKET,
+};
+
+const USAGE: &str = "usage: craftip-admin [--socket <path>] [--json] <command>
+
+commands:
+  list          list the connected tunnels
+  ban <key>     ban a public key and close its tunnel
+  reload        reload the config and the access list of the relay
+  drain         stop accepting new connections
+  stats         show statistics of the relay";
+
+struct Options {
+    socket: String,
+    json: bool,
+    request: AdminRequest,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut socket = DEFAULT_ADMIN_SOCKET.to_string();
+    let mut json = false;
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => socket = args.next().ok_or("--socket needs a path")?,
+            "--json" => json = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => command.push(arg),
+        }
+    }
+    let request = match command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["list"] => AdminRequest::List,
+        ["ban", key] => AdminRequest::Ban {
+            key: key.to_string(),
+        },
+        ["reload"] => AdminRequest::Reload,
+        ["drain"] => AdminRequest::Drain,
+        ["stats"] => AdminRequest::Stats,
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(Options {
+        socket,
+        json,
+        request,
+    })
+}
+
+#[cfg(unix)]
+async fn send(socket: &str, request: &AdminRequest) -> Result<AdminResponse, Box<dyn Error>> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| format!("could not connect to relay at {}: {}", socket, e))?;
+    let mut stream = BufReader::new(stream);
+    let mut request = serde_json::to_string(request)?;
+    request.push('\n');
+    stream.write_all(request.as_bytes()).await?;
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// the relay only serves the admin socket on unix
+#[cfg(not(unix))]
+async fn send(socket: &str, _request: &AdminRequest) -> Result<AdminResponse, Box<dyn Error>> {
+    Err(format!(
+        "could not connect to relay at {}: unix sockets are not supported on this platform",
+        socket
+    )
+    .into())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return ExitCode::FAILURE;
+        }
+    };
+    let response = match send(&options.socket, &options.request).await {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if options.json {
+        match serde_json::to_string_pretty(&response) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("{}", e),
+        }
+    } else {
+        print_response(&response);
+    }
+    match response {
+        AdminResponse::Error(_) => ExitCode::FAILURE,
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn print_response(response: &AdminResponse) {
+    match response {
+        AdminResponse::Tunnels(tunnels) => print_tunnels(tunnels),
+        AdminResponse::Banned(closed) if closed.is_empty() => {
+            println!("key banned, it had no open tunnel")
+        }
+        AdminResponse::Banned(closed) => println!("key banned, closed {}", closed.join(", ")),
+        AdminResponse::Reloaded => println!("config reloaded"),
+        AdminResponse::Draining => println!("relay is draining, new connections are refused"),
+        AdminResponse::Stats(stats) => print_stats(stats),
+        AdminResponse::Error(e) => eprintln!("error: {}", e),
+    }
+}
+
+fn print_tunnels(tunnels: &[TunnelInfo]) {
+    let now = unix_timestamp(SystemTime::now());
+    let rows = tunnels
+        .iter()
+        .map(|tunnel| {
+            vec![
+                tunnel.hostname.clone(),
+                tunnel.addr.to_string(),
+                format_duration(now.saturating_sub(tunnel.connected_since)),
+                tunnel.players.to_string(),
+                format_bytes(tunnel.uploaded),
+                format_bytes(tunnel.downloaded),
+            ]
+        })
+        .collect();
+    let headers = ["HOSTNAME", "ADDRESS", "CONNECTED", "PLAYERS", "UP", "DOWN"];
+    print_table(&headers, rows);
+}
+
+fn print_stats(stats: &RelayStats) {
+    let now = unix_timestamp(SystemTime::now());
+    let mut rows = vec![
+        vec![
+            "uptime".to_string(),
+            format_duration(now.saturating_sub(stats.started_at)),
+        ],
+        vec!["draining".to_string(), stats.draining.to_string()],
+        vec!["tunnels".to_string(), stats.tunnels.to_string()],
+        vec!["players".to_string(), stats.players.to_string()],
+        vec!["uploaded".to_string(), format_bytes(stats.uploaded)],
+        vec!["downloaded".to_string(), format_bytes(stats.downloaded)],
+    ];
+    for (reason, count) in &stats.rejected_connections {
+        rows.push(vec![format!("rejected {}", reason), count.to_string()]);
+    }
+    print_table(&["STAT", "VALUE"], rows);
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let headers = headers.iter().map(|header| header.to_string()).collect();
+    for row in std::iter::once(headers).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> impl Iterator<Item = String> + '_ {
+        args.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args("--json ban abc --socket /tmp/relay.sock")).unwrap();
+        assert!(options.json);
+        assert_eq!(options.socket, "/tmp/relay.sock");
+        assert!(matches!(options.request, AdminRequest::Ban { key } if key == "abc"));
+        let options = parse_args(args("stats")).unwrap();
+        assert_eq!(options.socket, DEFAULT_ADMIN_SOCKET);
+        assert!(parse_args(args("ban")).is_err());
+        assert!(parse_args(args("")).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(3661), "1h 1m");
+        assert_eq!(format_duration(90000), "1d 1h");
+    }
+}
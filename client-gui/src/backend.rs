@@ -7,7 +7,8 @@ use crate::gui_channel::ServerState;
 use crate::GuiState;
 use client::client::Client;
 use client::structs::ControlTx;
-use client::structs::{Control, Stats};
+use client::structs::{Control, ServerAuthentication, Stats};
+use shared::crypto::ServerPrivateKey;
 
 pub struct Controller {
     pub gui_rx: UnboundedReceiver<GuiTriggeredEvent>,
@@ -39,6 +40,29 @@ impl Controller {
                         }
                         Stats::Connected => {}
                         Stats::Ping(_ping) => {}
+                        Stats::CertificatePinned(fingerprint) => {
+                            tracing::info!(fingerprint, "certificate of the relay pinned");
+                            self.state.lock().unwrap().set_active_server(|s| {
+                                s.relay_fingerprint = Some(fingerprint);
+                            }).unwrap();
+                        }
+                        Stats::Compression { sent, received } => {
+                            tracing::debug!(sent = sent.ratio(), received = received.ratio(), "compression ratio");
+                        }
+                        Stats::PlayerJoined(join) => {
+                            let username = join.login.map(|login| login.name);
+                            tracing::info!(client_id = join.client_id, username = username.as_deref(), "player joined");
+                        }
+                        Stats::PlayerLeft(client_id) => {
+                            tracing::info!(client_id, "player left");
+                        }
+                        Stats::KeyRotated(key) => {
+                            tracing::info!("Key has been rotated");
+                            self.state.lock().unwrap().set_active_server(|s| {
+                                s.auth = ServerAuthentication::Key(key);
+                                s.pending_key = None;
+                            }).unwrap();
+                        }
                     }
                 }
                 event = self.gui_rx.recv() => {
@@ -90,6 +114,17 @@ impl Controller {
                                 }).unwrap();
                             });
                         }
+                        GuiTriggeredEvent::RotateKey() => {
+                            // stored first, the rotation is sent again on the next connect
+                            // until the relay confirms it
+                            let key = ServerPrivateKey::default();
+                            self.state.lock().unwrap().set_active_server(|s| {
+                                s.pending_key = Some(key.clone());
+                            }).unwrap();
+                            if let Some(control_tx) = &control_tx {
+                                control_tx.send(Control::RotateKey(key)).unwrap();
+                            }
+                        }
                         GuiTriggeredEvent::Disconnect() => {
                             // sleep async 1 sec
                             if let Some(control_tx) = &control_tx {
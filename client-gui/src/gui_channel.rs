@@ -8,6 +8,8 @@ pub type GuiTriggeredChannel = mpsc::UnboundedSender<GuiTriggeredEvent>;
 pub enum GuiTriggeredEvent {
     Connect(Server),
     Disconnect(),
+    /// replaces the key of the connected server, the server ip stays the same
+    RotateKey(),
 }
 
 impl From<&ServerPanel> for Server {
@@ -16,6 +18,15 @@ impl From<&ServerPanel> for Server {
             server: server_panel.server.clone(),
             local: server_panel.local.clone(),
             auth: server_panel.auth.clone(),
+            domains: server_panel.domains.clone(),
+            player_filter: server_panel.player_filter.clone(),
+            flush_delay: server_panel.flush_delay,
+            compression: server_panel.compression,
+            connections: server_panel.connections,
+            transport: server_panel.transport,
+            max_players: server_panel.max_players,
+            relay_fingerprint: server_panel.relay_fingerprint.clone(),
+            pending_key: server_panel.pending_key.clone(),
         }
     }
 }
@@ -13,20 +13,15 @@ use eframe::{egui, CreationContext, Storage, Theme};
 use tokio::sync::mpsc;
 
 use crate::gui_channel::{GuiTriggeredChannel, GuiTriggeredEvent, ServerState};
-use client::structs::{Server, ServerAuthentication};
+use client::structs::{Server, ServerAuthentication, Transport};
 use shared::crypto::ServerPrivateKey;
+use shared::logging::init_logging;
+use shared::player_filter::PlayerFilter;
 
 #[tokio::main]
 pub async fn main() -> Result<(), eframe::Error> {
     // Log to stdout (if you run with `RUST_LOG=debug`).
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(false)
-        .with_target(false)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let _telemetry = init_logging("craftip-client", "info").unwrap();
 
     let mut viewport = egui::ViewportBuilder::default()
         .with_inner_size([500.0, 400.0]);
@@ -215,6 +210,15 @@ impl eframe::App for MyApp {
 struct ServerPanel {
     server: String,
     auth: ServerAuthentication,
+    domains: Vec<String>,
+    player_filter: Option<PlayerFilter>,
+    flush_delay: Option<u64>,
+    compression: bool,
+    connections: Option<u8>,
+    transport: Transport,
+    max_players: Option<u16>,
+    relay_fingerprint: Option<String>,
+    pending_key: Option<ServerPrivateKey>,
     connected: u16,
     local: String,
     edit_local: Option<String>,
@@ -229,6 +233,15 @@ impl From<&Server> for ServerPanel {
             state: ServerState::Disconnected,
             server: server.server.clone(),
             auth: server.auth.clone(),
+            domains: server.domains.clone(),
+            player_filter: server.player_filter.clone(),
+            flush_delay: server.flush_delay,
+            compression: server.compression,
+            connections: server.connections,
+            transport: server.transport,
+            max_players: server.max_players,
+            relay_fingerprint: server.relay_fingerprint.clone(),
+            pending_key: server.pending_key.clone(),
             connected: 0,
             local: server.local.clone(),
             error: None,
@@ -324,6 +337,14 @@ impl ServerPanel {
                                         .color(Color32::from_rgb(0, 204, 0)),
                                 );
                                 ui.label("🔌");
+                                if ui
+                                    .button("🔑")
+                                    .on_hover_text("Replace the key of this server, the server IP stays the same")
+                                    .clicked()
+                                {
+                                    tx.send(GuiTriggeredEvent::RotateKey())
+                                        .expect("failed to send rotate key event");
+                                }
                             }
                         }
                     });
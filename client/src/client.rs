@@ -1,32 +1,51 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::time::Duration;
 use std::time::SystemTime;
 
 use anyhow::{bail, Context, Result};
 use futures::SinkExt;
-use shared::config::PROTOCOL_VERSION;
+use quinn::{Connection, ConnectionError, RecvStream, SendStream};
+use shared::config::{DOMAIN_CHALLENGE_SUBDOMAIN, PROTOCOL_VERSION};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::{sleep, timeout};
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
+use tracing::Instrument;
 
+use shared::addressing::DistributorError;
+use shared::batching::{WriteBatcher, DEFAULT_FLUSH_DELAY};
+use shared::compression::CompressionAlgorithm;
+use shared::crypto::ServerPrivateKey;
+use shared::lane::run_lane;
 use shared::packet_codec::PacketCodec;
-use shared::proxy::{ProxyAuthenticator, ProxyDataPacket, ProxyHelloPacket};
+use shared::proxy::{ProxyAuthenticator, ProxyClientJoinPacket, ProxyDataPacket, ProxyHelloPacket};
+use shared::quic::{peer_fingerprint, QuicStream};
+use shared::scheduler::FairQueue;
 use shared::socket_packet::SocketPacket;
 
 use crate::connection_handler::ClientConnection;
 use crate::structs::{
-    ClientError, ClientToProxy, Control, ControlRx, ProxyToClient, ProxyToClientTx, Server,
-    ServerAuthentication, Stats, StatsTx,
+    ClientError, ClientToProxy, Control, ControlRx, LaneEvent, ProxyToClient, ProxyToClientTx,
+    Server, ServerAuthentication, Stats, StatsTx, Transport,
 };
+use crate::transport::{self, ProxyStream};
 
 pub struct Client {
     state: State,
     stats_tx: StatsTx,
-    proxy: Option<Framed<TcpStream, PacketCodec>>,
+    proxy: Option<Framed<ProxyStream, PacketCodec>>,
+    /// QUIC connection the relay opens the streams of the players on
+    quic: Option<Connection>,
+    /// compression the relay selected for the tunnel
+    compression: Option<CompressionAlgorithm>,
     control_rx: ControlRx,
     server: Server,
+    /// connections the relay accepts for the tunnel, including the first one
+    connections: u8,
 }
 
 #[derive(Default)]
@@ -39,18 +58,22 @@ impl State {
     pub fn set_stats_tx(&mut self, tx: StatsTx) {
         self.stats_tx = Some(tx);
     }
-    pub fn add_connection(&mut self, id: u16, tx: ProxyToClientTx) {
-        self.connections.insert(id, tx);
+    pub fn add_connection(&mut self, join: ProxyClientJoinPacket, tx: ProxyToClientTx) {
+        self.connections.insert(join.client_id, tx);
         if let Some(tx) = &self.stats_tx {
             tx.send(Stats::ClientsConnected(self.connections.len() as u16))
                 .unwrap();
+            tx.send(Stats::PlayerJoined(join)).unwrap();
         }
     }
     pub fn remove_connection(&mut self, id: u16) {
-        self.connections.remove(&id);
+        let removed = self.connections.remove(&id).is_some();
         if let Some(tx) = &self.stats_tx {
             tx.send(Stats::ClientsConnected(self.connections.len() as u16))
                 .unwrap();
+            if removed {
+                tx.send(Stats::PlayerLeft(id)).unwrap();
+            }
         }
     }
     pub fn send_to(&mut self, id: u16, msg: ProxyToClient) -> Result<()> {
@@ -75,57 +98,64 @@ impl Client {
             state,
             control_rx,
             proxy: None,
+            quic: None,
+            compression: None,
+            connections: 1,
         }
     }
 }
 
 impl Client {
+    #[tracing::instrument(name = "tunnel", skip_all, fields(hostname = %self.server.server))]
     pub async fn connect(&mut self) -> Result<(), ClientError> {
         // test connection to minecraft server
         TcpStream::connect(&self.server.local)
             .await
             .map_err(|_| ClientError::MinecraftServerNotFound)?;
-        // connect to proxy
-        let proxy_stream = TcpStream::connect(format!("{}:25565", &self.server.server)).await?;
-        let mut proxy = Framed::new(proxy_stream, PacketCodec::new(1024 * 4));
-
-        let hello = SocketPacket::from(ProxyHelloPacket {
-            version: PROTOCOL_VERSION,
-            hostname: self.server.server.clone(),
-            auth: match &mut self.server.auth {
-                ServerAuthentication::Key(private_key) => {
-                    ProxyAuthenticator::PublicKey(private_key.get_public_key())
-                }
-            },
-        });
-
-        proxy.send(hello).await?;
-        let challenge = match timeout(Duration::from_secs(10), proxy.next()).await {
-            Ok(Some(Ok(SocketPacket::ProxyAuthRequest(pkg)))) => pkg,
-            Err(_) => return Err(ClientError::Timeout),
-            Ok(e) => return Err(ClientError::UnexpectedPacket(format!("{:?}", e))),
-        };
-
-        match &mut self.server.auth {
-            ServerAuthentication::Key(private_key) => {
-                let signature = private_key.sign(&challenge);
-                proxy
-                    .send(SocketPacket::ProxyAuthResponse(signature))
-                    .await?;
-            }
-        }
-
-        tokio::select! {
-            res = proxy.next() => match res {
-                Some(Ok(SocketPacket::ProxyHelloResponse(_hello_response))) => Ok(()),
-                Some(Ok(SocketPacket::ProxyError(e))) => Err(ClientError::ProxyError(e)),
-                None => Err(ClientError::ProxyClosedConnection),
-                Some(Err(e)) => Err(ClientError::ProtocolError(e)),
-                e => return Err(ClientError::UnexpectedPacket(format!("{:?}", e))),
-            }?,
-            res = self.control_rx.recv() => match res {
-                Some(Control::Disconnect) | None => {
-                    return Err(ClientError::UserClosedConnection)
+        let (mut proxy, quic) = open_connection(&self.server, false).await?;
+        self.connections = 1;
+        self.compression = None;
+        loop {
+            tokio::select! {
+                res = proxy.next() => match res {
+                    Some(Ok(SocketPacket::ProxyHelloResponse(_hello_response))) => break,
+                    // the data after the hello response is compressed
+                    Some(Ok(SocketPacket::ProxyCompressionSelected(algorithm))) => {
+                        tracing::info!("tunnel is compressed with {:?}", algorithm);
+                        proxy.codec_mut().set_compression(algorithm);
+                        self.compression = Some(algorithm);
+                    }
+                    Some(Ok(SocketPacket::ProxyConnectionsGranted(count))) => {
+                        tracing::info!("relay accepts {} connections for the tunnel", count);
+                        self.connections = count;
+                    }
+                    Some(Ok(SocketPacket::ProxyPlayerLimitGranted(limit))) => {
+                        tracing::info!("relay lets {} players into the tunnel", limit);
+                    }
+                    Some(Ok(SocketPacket::ProxyError(e))) => {
+                        // the relay rotated the key but the confirmation got lost
+                        if e == DistributorError::KeyRevoked.to_string() {
+                            if let Some(key) = self.server.pending_key.take() {
+                                tracing::info!("Key has been rotated before, connecting with the new key");
+                                self.server.auth = ServerAuthentication::Key(key.clone());
+                                self.stats_tx
+                                    .send(Stats::KeyRotated(key))
+                                    .map_err(|e| ClientError::Other(e.into()))?;
+                                return Box::pin(self.connect()).await;
+                            }
+                        }
+                        return Err(ClientError::ProxyError(e));
+                    }
+                    None => return Err(ClientError::ProxyClosedConnection),
+                    Some(Err(e)) => return Err(ClientError::ProtocolError(e)),
+                    e => return Err(ClientError::UnexpectedPacket(format!("{:?}", e))),
+                },
+                res = self.control_rx.recv() => match res {
+                    Some(Control::Disconnect) | None => {
+                        return Err(ClientError::UserClosedConnection)
+                    }
+                    // sent as soon as the connection is established
+                    Some(Control::RotateKey(key)) => self.server.pending_key = Some(key),
                 }
             }
         }
@@ -133,86 +163,414 @@ impl Client {
         self.stats_tx
             .send(Stats::Connected)
             .map_err(|e| ClientError::Other(e.into()))?;
+        // trust on first use, later connections have to present the same certificate
+        if let (None, Some(connection)) = (&self.server.relay_fingerprint, &quic) {
+            if let Some(fingerprint) = peer_fingerprint(connection) {
+                tracing::info!(fingerprint, "pinning the certificate of the relay");
+                self.server.relay_fingerprint = Some(fingerprint.clone());
+                self.stats_tx
+                    .send(Stats::CertificatePinned(fingerprint))
+                    .map_err(|e| ClientError::Other(e.into()))?;
+            }
+        }
         self.proxy = Some(proxy);
+        self.quic = quic;
         Ok(())
     }
+    #[tracing::instrument(name = "tunnel", skip_all, fields(hostname = %self.server.server))]
     pub async fn handle(&mut self) -> Result<()> {
         let (to_proxy_tx, mut to_proxy_rx) = mpsc::unbounded_channel();
+        let (lane_events_tx, mut lane_events_rx) = mpsc::unbounded_channel();
         let proxy = self.proxy.as_mut().unwrap();
+        let flush_delay = self.server.flush_delay.map(Duration::from_millis);
+        let flush_delay = flush_delay.unwrap_or(DEFAULT_FLUSH_DELAY);
+        let mut batcher = WriteBatcher::new(flush_delay);
+        let quic = self.quic.clone();
+        // extra TCP connections of the tunnel, reopened on the next ping if they close
+        let tcp_lanes = self.connections.saturating_sub(1) as usize;
+        // the extra connections and the QUIC streams of the players by their index
+        let mut lanes: HashMap<usize, UnboundedSender<SocketPacket>> = HashMap::new();
+        let mut next_stream = tcp_lanes;
+        // the relay decides which connection a player uses, its packets are sent back there
+        let mut player_lanes: HashMap<u16, usize> = HashMap::new();
+        // packets of the players by their id, written in turns
+        let mut queue: FairQueue<u16, SocketPacket> = FairQueue::default();
+        // the proxy usually sends the reason before closing the connection
+        let mut last_error = None;
+        for domain in &self.server.domains {
+            proxy
+                .send(SocketPacket::ProxyDomainRequest(domain.clone()))
+                .await?;
+        }
+        if let Some(filter) = &self.server.player_filter {
+            proxy
+                .send(SocketPacket::ProxyPlayerFilter(filter.clone()))
+                .await?;
+        }
+        // a rotation the relay has not confirmed yet is sent again
+        if let Some(key) = &self.server.pending_key {
+            proxy.send(create_rotation(&self.server, key)).await?;
+        }
+        for index in 0..tcp_lanes {
+            let open = open_lane(self.server.clone());
+            let lane = spawn_lane(open, index, lane_events_tx.clone(), flush_delay);
+            lanes.insert(index, lane);
+        }
         loop {
-            tokio::select! {
+            // packets of the tunnel and the extra connection they were received on
+            let (lane, msg) = tokio::select! {
                 // process control messages e.g. form gui
                 result = self.control_rx.recv() => {
                     match result {
                         Some(Control::Disconnect) | None => {
                             return Ok(());
                         }
+                        Some(Control::RotateKey(key)) => {
+                            proxy.send(create_rotation(&self.server, &key)).await?;
+                            self.server.pending_key = Some(key);
+                        }
                     }
+                    continue;
                 }
-                // send packets to proxy
+                // queue the packets of the players
                Some(pkg) = to_proxy_rx.recv() => {
                     //tracing::info!("Sending packet to client: {:?}", pkg);
-                    match pkg {
-                        ClientToProxy::Packet(id, pkg) => {
-                            proxy.send(SocketPacket::from(ProxyDataPacket::new(pkg, id))).await?;
-                        },
-                        ClientToProxy::RemoveMinecraftClient(id) => {
-                            proxy.send(SocketPacket::ProxyDisconnect(id)).await?;
-                            self.state.remove_connection(id);
-                        },
-                        ClientToProxy::Death(msg) => {
-                            bail!(msg);
+                    enqueue(&mut self.state, &mut queue, pkg)?;
+                    continue;
+                }
+                // write the packets of the players, taking turns between them
+                _ = std::future::ready(()), if !queue.is_empty() => {
+                    // queue everything the players have sent so far, the queue can only
+                    // reorder the packets it holds
+                    while let Ok(pkg) = to_proxy_rx.try_recv() {
+                        enqueue(&mut self.state, &mut queue, pkg)?;
+                    }
+                    let (id, packet) = queue.pop().expect("queue is not empty");
+                    let lane = player_lanes.get(&id).and_then(|lane| lanes.get(lane));
+                    let disconnect = matches!(packet, SocketPacket::ProxyDisconnect(_));
+                    match lane {
+                        Some(lane) => {
+                            let _ = lane.send(packet);
+                        }
+                        None if matches!(packet, SocketPacket::ProxyData(_)) => {
+                            batcher.feed(proxy, packet).await?;
                         }
+                        None => proxy.send(packet).await?,
+                    }
+                    if disconnect {
+                        player_lanes.remove(&id);
                     }
+                    continue;
+                }
+                // write the data frames of the players together
+                _ = batcher.expired() => {
+                    batcher.flush(proxy).await?;
+                    continue;
+                }
+                // receive packets of the extra connections
+                Some(event) = lane_events_rx.recv() => match event {
+                    LaneEvent::Packet(lane, msg) => (Some(lane), msg),
+                    LaneEvent::Closed(lane) => {
+                        lanes.remove(&lane);
+                        // the players continue, their packets are sent on the first connection
+                        player_lanes.retain(|_, &mut player_lane| player_lane != lane);
+                        continue;
+                    }
+                },
+                // the relay opens a QUIC stream for every player that joins
+                stream = accept_stream(quic.as_ref()) => {
+                    let stream = QuicStream::new(stream?);
+                    let mut framed = Framed::new(stream, PacketCodec::proxy(1024 * 4));
+                    if let Some(algorithm) = self.compression {
+                        framed.codec_mut().set_compression(algorithm);
+                    }
+                    let open = std::future::ready(Ok(framed));
+                    let lane = spawn_lane(open, next_stream, lane_events_tx.clone(), flush_delay);
+                    lanes.insert(next_stream, lane);
+                    next_stream += 1;
+                    continue;
                 }
                 // receive proxy packets
                 result = proxy.next() => {
                     match result {
-                        Some(Ok(msg)) => {
-                            match msg {
-                                SocketPacket::ProxyJoin(client_id) => {
-                                    let (mut client_connection, client_tx) = ClientConnection::new(to_proxy_tx.clone(), self.server.local.clone(), client_id).await;
-                                    self.state.add_connection(client_id, client_tx);
-                                    tokio::spawn(async move {
-                                        if let Err(e) = client_connection.handle_client().await {
-                                            tracing::error!("An Error occurred in the handle_client function: {}", e);
-                                            // sometimes handle_client closes after gui, errors can occur
-                                            client_connection.set_death(e.to_string());
-                                        }
-                                    });
-                                }
-                                SocketPacket::ProxyData(packet) => {
-                                    self.state.send_to(packet.client_id, packet.packet)?;
-                                }
-                                SocketPacket::ProxyDisconnect(client_id) => {
-                                    // this can fail if the client is already disconnected
-                                    self.state.remove_connection(client_id);
-                                }
-                                SocketPacket::ProxyPong(ping) => {
-                                    let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u16;
-                                    let ping = time.saturating_sub(ping);
-                                    self.stats_tx.send(Stats::Ping(ping))?;
-                                }
-                                _ => unimplemented!("Message not implemented!")
-                            }
-                        }
+                        Some(Ok(msg)) => (None, msg),
                         // An error occurred.
                         Some(Err(e)) => bail!("an error occurred while processing messages error = {:?}", e),
                         // The stream has been exhausted.
-                        None => bail!("Proxy has closed the connection")
+                        None => match last_error {
+                            Some(e) => bail!("Proxy has closed the connection: {}", e),
+                            None => bail!("Proxy has closed the connection"),
+                        }
                     }
                 },
                 // ensure constant traffic so tcp connection does not close
                 _ = sleep(Duration::from_secs(1)) => {
                     let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u16;
                     proxy.send(SocketPacket::ProxyPing(time)).await?;
+                    if let Some((sent, received)) = proxy.codec().compression_stats() {
+                        self.stats_tx.send(Stats::Compression { sent, received })?;
+                    }
+                    for index in 0..tcp_lanes {
+                        lanes.entry(index).or_insert_with(|| {
+                            let open = open_lane(self.server.clone());
+                            spawn_lane(open, index, lane_events_tx.clone(), flush_delay)
+                        });
+                    }
                     continue;
                 }
+            };
+            match msg {
+                SocketPacket::ProxyJoin(join) => {
+                    let client_id = join.client_id;
+                    if let Some(lane) = lane {
+                        player_lanes.insert(client_id, lane);
+                    }
+                    let username = join.login.as_ref().map(|login| login.name.clone());
+                    let (mut client_connection, client_tx) = ClientConnection::new(
+                        to_proxy_tx.clone(),
+                        self.server.local.clone(),
+                        client_id,
+                    )
+                    .await;
+                    self.state.add_connection(join, client_tx);
+                    let span =
+                        tracing::info_span!("player", client_id, username = username.as_deref());
+                    tokio::spawn(
+                        async move {
+                            if let Err(e) = client_connection.handle_client().await {
+                                tracing::error!(error = %e, "An Error occurred in the handle_client function");
+                                // sometimes handle_client closes after gui, errors can occur
+                                client_connection.set_death(e.to_string());
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+                SocketPacket::ProxyData(packet) => {
+                    self.state.send_to(packet.client_id, packet.packet)?;
+                }
+                SocketPacket::ProxyDisconnect(client_id) => {
+                    // this can fail if the client is already disconnected
+                    self.state.remove_connection(client_id);
+                    player_lanes.remove(&client_id);
+                    queue.remove(&client_id);
+                }
+                SocketPacket::ProxyPong(ping) => {
+                    let time = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u16;
+                    let ping = time.saturating_sub(ping);
+                    self.stats_tx.send(Stats::Ping(ping))?;
+                }
+                SocketPacket::ProxyKeyRotated(public_key) => match self.server.pending_key.take() {
+                    Some(key) if key.get_public_key() == public_key => {
+                        tracing::info!(
+                            "Key has been rotated, {} stays the same",
+                            self.server.server
+                        );
+                        self.server.auth = ServerAuthentication::Key(key.clone());
+                        self.stats_tx.send(Stats::KeyRotated(key))?;
+                    }
+                    _ => tracing::warn!("Proxy rotated to an unknown key {}", public_key),
+                },
+                SocketPacket::ProxyDomainVerified(domain) => {
+                    tracing::info!("Domain {} is now routed to this server", domain);
+                }
+                SocketPacket::ProxyDraining(deadline) => {
+                    let message = match deadline {
+                        Some(seconds) => format!(
+                            "Relay is shutting down, the tunnel will be closed in {}s",
+                            seconds
+                        ),
+                        None => "Relay is draining and does not accept new connections".to_string(),
+                    };
+                    tracing::warn!("{}", message);
+                    last_error = Some(message);
+                }
+                SocketPacket::ProxyError(e) => {
+                    tracing::warn!("Proxy error: {}", e);
+                    log_domain_challenge(&self.server, &e);
+                    last_error = Some(e);
+                }
+                // packets that are only expected during the handshake or only sent by clients
+                packet => tracing::warn!("ignoring unexpected packet {:?}", packet),
             }
         }
     }
 }
 
+/// connects to the relay and authenticates with the key of the server,
+/// extra connections of a tunnel are attached to the tunnel of the first one
+async fn open_connection(
+    server: &Server,
+    attach: bool,
+) -> Result<(Framed<ProxyStream, PacketCodec>, Option<Connection>), ClientError> {
+    let (proxy_stream, quic) = transport::connect(server).await?;
+    let mut proxy = Framed::new(proxy_stream, PacketCodec::proxy(1024 * 4));
+
+    let hello = SocketPacket::from(ProxyHelloPacket {
+        version: PROTOCOL_VERSION,
+        hostname: server.server.clone(),
+        auth: match &server.auth {
+            ServerAuthentication::Key(private_key) => {
+                ProxyAuthenticator::PublicKey(private_key.get_public_key())
+            }
+        },
+    });
+
+    proxy.send(hello).await?;
+    let connections = server.connections.unwrap_or(1);
+    if attach {
+        proxy.send(SocketPacket::ProxyAttach).await?;
+    } else if connections > 1 && server.transport == Transport::Tcp {
+        proxy
+            .send(SocketPacket::ProxyConnectionsRequest(connections))
+            .await?;
+    }
+    if let Some(limit) = server.max_players.filter(|_| !attach) {
+        proxy
+            .send(SocketPacket::ProxyPlayerLimitRequest(limit))
+            .await?;
+    }
+    if server.compression {
+        let offer = vec![CompressionAlgorithm::Deflate];
+        proxy
+            .send(SocketPacket::ProxyCompressionOffer(offer))
+            .await?;
+    }
+    let challenge = match timeout(Duration::from_secs(10), proxy.next()).await {
+        Ok(Some(Ok(SocketPacket::ProxyAuthRequest(pkg)))) => pkg,
+        Err(_) => return Err(ClientError::Timeout),
+        Ok(e) => return Err(ClientError::UnexpectedPacket(format!("{:?}", e))),
+    };
+
+    match &server.auth {
+        ServerAuthentication::Key(private_key) => {
+            let signature = private_key.sign(&challenge);
+            proxy
+                .send(SocketPacket::ProxyAuthResponse(signature))
+                .await?;
+        }
+    }
+    Ok((proxy, quic))
+}
+
+/// opens an extra connection of the tunnel
+async fn open_lane(server: Server) -> Result<Framed<ProxyStream, PacketCodec>, ClientError> {
+    let (mut proxy, _) = open_connection(&server, true).await?;
+    loop {
+        match timeout(Duration::from_secs(10), proxy.next()).await {
+            Ok(Some(Ok(SocketPacket::ProxyHelloResponse(_)))) => return Ok(proxy),
+            Ok(Some(Ok(SocketPacket::ProxyCompressionSelected(algorithm)))) => {
+                proxy.codec_mut().set_compression(algorithm);
+            }
+            Ok(Some(Ok(SocketPacket::ProxyError(e)))) => return Err(ClientError::ProxyError(e)),
+            Ok(None) => return Err(ClientError::ProxyClosedConnection),
+            Err(_) => return Err(ClientError::Timeout),
+            Ok(e) => return Err(ClientError::UnexpectedPacket(format!("{:?}", e))),
+        }
+    }
+}
+
+/// waits for the relay to open the stream of a player, never returns without QUIC
+async fn accept_stream(
+    quic: Option<&Connection>,
+) -> Result<(SendStream, RecvStream), ConnectionError> {
+    match quic {
+        Some(connection) => connection.accept_bi().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// opens an extra connection of the tunnel in the background, the packets received on it
+/// are sent to `events`, returns the channel for the packets to send on it
+fn spawn_lane<F, T>(
+    open: F,
+    index: usize,
+    events: UnboundedSender<LaneEvent>,
+    flush_delay: Duration,
+) -> UnboundedSender<SocketPacket>
+where
+    F: Future<Output = Result<Framed<T, PacketCodec>, ClientError>> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let span = tracing::info_span!("lane", index);
+    tokio::spawn(
+        async move {
+            let result = match open.await {
+                Ok(mut framed) => {
+                    let mut batcher = WriteBatcher::new(flush_delay);
+                    run_lane(&mut framed, &mut rx, &mut batcher, |packet| {
+                        events.send(LaneEvent::Packet(index, packet)).is_ok()
+                    })
+                    .await
+                    .map_err(ClientError::from)
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "connection of the tunnel closed");
+            }
+            let _ = events.send(LaneEvent::Closed(index));
+        }
+        .instrument(span),
+    );
+    tx
+}
+
+/// queues a message of a player for the relay
+fn enqueue(
+    state: &mut State,
+    queue: &mut FairQueue<u16, SocketPacket>,
+    message: ClientToProxy,
+) -> Result<()> {
+    match message {
+        ClientToProxy::Packet(id, pkg) => {
+            let size = pkg.data.len();
+            queue.push(id, size, SocketPacket::from(ProxyDataPacket::new(pkg, id)));
+        }
+        ClientToProxy::RemoveMinecraftClient(id) => {
+            state.remove_connection(id);
+            queue.push(id, 0, SocketPacket::ProxyDisconnect(id));
+        }
+        ClientToProxy::Death(msg) => {
+            bail!(msg);
+        }
+    }
+    Ok(())
+}
+
+/// signs the handover of the hostname from the current key to `new_key`
+fn create_rotation(server: &Server, new_key: &ServerPrivateKey) -> SocketPacket {
+    let ServerAuthentication::Key(private_key) = &server.auth;
+    SocketPacket::ProxyKeyRotation(private_key.rotate_to(&new_key.get_public_key()))
+}
+
+/// prints the TXT record that is needed if the error is about the ownership of a custom domain
+fn log_domain_challenge(server: &Server, error: &str) {
+    let ServerAuthentication::Key(private_key) = &server.auth;
+    let public_key = private_key.get_public_key();
+    // the relay lowercases the domain and strips a trailing dot before verifying it
+    let domains = server
+        .domains
+        .iter()
+        .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase());
+    for domain in domains {
+        if error != DistributorError::DomainNotVerified(domain.clone()).to_string() {
+            continue;
+        }
+        tracing::info!(
+            "to use {} add the TXT record {}.{} with the content \"{}\"",
+            domain,
+            DOMAIN_CHALLENGE_SUBDOMAIN,
+            domain,
+            public_key.get_domain_challenge(&domain)
+        );
+    }
+}
+
 impl Drop for Client {
     fn drop(&mut self) {
         tracing::info!("Proxy client dropped");
@@ -1,12 +1,17 @@
 use anyhow::{Context, Result};
+use bytes::BytesMut;
 use shared::minecraft::MinecraftDataPacket;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::Instrument;
 
 use crate::structs::{ClientToProxy, ClientToProxyTx, ProxyToClientRx, ProxyToClientTx};
 use shared::socket_packet::SocketPacket;
 
+/// bytes read from the minecraft server at once, stays well below the limit of a proxy packet
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
 pub type Tx = UnboundedSender<Option<SocketPacket>>;
 pub struct ClientConnection {
     mc_server: String,
@@ -35,10 +40,11 @@ impl ClientConnection {
         )
     }
     pub async fn handle_client(&mut self) -> Result<()> {
-        tracing::info!("opening new client with id {}", self.client_id);
+        tracing::info!("opening new client");
         // connect to server
-        let mut buf = [0; 1024];
+        let mut buf = BytesMut::with_capacity(READ_BUFFER_SIZE);
         let mut mc_server = TcpStream::connect(&self.mc_server)
+            .instrument(tracing::info_span!("connect_local", server = %self.mc_server))
             .await
             .context(format!("could not connect to {}", &self.mc_server))?;
         loop {
@@ -58,7 +64,7 @@ impl ClientConnection {
                         }
                     }
                 }
-                n = mc_server.read(&mut buf) => {
+                n = mc_server.read_buf(&mut buf) => {
                     let n = match n {
                         Ok(n) => n,
                         Err(err) => {
@@ -72,12 +78,16 @@ impl ClientConnection {
                     }
                     tracing::debug!("recv pkg from mc srv len: {}", n);
                     // encapsulate in ProxyDataPacket
-                    let packet = ClientToProxy::Packet(self.client_id, MinecraftDataPacket { data: buf[0..n].to_vec() });
+                    // the packet takes the read bytes without copying them
+                    let data = buf.split().freeze();
+                    let packet = ClientToProxy::Packet(self.client_id, MinecraftDataPacket { data });
 
                     if let Err(e) = self.proxy_tx.send(packet) {
                         tracing::error!("tx send failed: {}", e);
                         break;
                     }
+                    // reuses the memory once the proxy sent the earlier packets
+                    buf.reserve(READ_BUFFER_SIZE);
                 }
             }
         }
@@ -99,7 +109,7 @@ impl ClientConnection {
 
 impl Drop for ClientConnection {
     fn drop(&mut self) {
-        tracing::info!("dropping client connection {}", self.client_id);
+        tracing::info!("dropping client connection");
         if self.need_for_close {
             let _ = self
                 .proxy_tx
@@ -1,3 +1,4 @@
 pub mod client;
 pub mod connection_handler;
 pub mod structs;
+pub mod transport;
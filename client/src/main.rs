@@ -2,21 +2,13 @@ use anyhow::Result;
 use client::client::Client;
 use client::structs::{Server, ServerAuthentication};
 use shared::crypto::ServerPrivateKey;
+use shared::logging::init_logging;
 use tokio::sync::mpsc;
 
 #[tokio::main]
 pub async fn main() -> Result<()> {
     // Log to stdout (if you run with `RUST_LOG=debug`).
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(false)
-        .with_target(false)
-        .without_time()
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let _telemetry = init_logging("craftip-client", "info").unwrap();
     tracing::info!("Starting client...");
 
     let private_key = ServerPrivateKey::default();
@@ -24,6 +16,15 @@ pub async fn main() -> Result<()> {
         server: private_key.get_public_key().get_hostname(),
         local: "localhost:25564".to_string(),
         auth: ServerAuthentication::Key(private_key),
+        domains: Vec::new(),
+        player_filter: None,
+        flush_delay: None,
+        compression: false,
+        connections: None,
+        transport: Default::default(),
+        max_players: None,
+        relay_fingerprint: None,
+        pending_key: None,
     };
     tracing::info!("Connecting to server: {}", server.server);
 
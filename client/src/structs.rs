@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
+use shared::compression::CompressionStats;
 use shared::crypto::ServerPrivateKey;
 use shared::minecraft::MinecraftDataPacket;
 use shared::packet_codec::PacketCodecError;
+use shared::player_filter::PlayerFilter;
+use shared::proxy::ProxyClientJoinPacket;
+use shared::socket_packet::SocketPacket;
 use std::io;
 use thiserror::Error;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
@@ -11,11 +15,27 @@ pub enum Stats {
     Connected,
     ClientsConnected(u16),
     Ping(u16),
+    /// a player joined, contains the name if the player is logging in
+    PlayerJoined(ProxyClientJoinPacket),
+    PlayerLeft(u16),
+    /// the relay accepted the new key, it has to be stored instead of the old one
+    KeyRotated(ServerPrivateKey),
+    /// the certificate of the relay has been trusted on first use, its fingerprint has to be
+    /// stored as `relay_fingerprint`
+    CertificatePinned(String),
+    /// size of the data sent and received through the compressed tunnel
+    Compression {
+        sent: CompressionStats,
+        received: CompressionStats,
+    },
 }
 
 #[derive(Debug)]
 pub enum Control {
     Disconnect,
+    /// replaces the key of the server while keeping its hostname, the key has to be stored
+    /// as `pending_key` before in case the relay does not confirm it
+    RotateKey(ServerPrivateKey),
 }
 
 #[derive(Error, Debug)]
@@ -45,6 +65,12 @@ pub enum ClientToProxy {
     RemoveMinecraftClient(u16),
     Death(String),
 }
+/// what happened on an extra connection of the tunnel, identified by its index
+pub enum LaneEvent {
+    Packet(usize, SocketPacket),
+    Closed(usize),
+}
+
 pub type ClientToProxyRx = UnboundedReceiver<ClientToProxy>;
 pub type ClientToProxyTx = UnboundedSender<ClientToProxy>;
 pub type ProxyToClient = MinecraftDataPacket;
@@ -61,6 +87,47 @@ pub struct Server {
     pub server: String,
     pub local: String,
     pub auth: ServerAuthentication,
+    /// custom domains the relay should route to this server
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// players the relay lets in, everybody if not set
+    #[serde(default)]
+    pub player_filter: Option<PlayerFilter>,
+    /// milliseconds the frames of the players may wait to be sent together, 1 if not set
+    #[serde(default)]
+    pub flush_delay: Option<u64>,
+    /// offers the relay to compress the data of the players
+    #[serde(default)]
+    pub compression: bool,
+    /// connections opened to the relay, so a lost packet only stalls some of the players,
+    /// 1 if not set
+    #[serde(default)]
+    pub connections: Option<u8>,
+    #[serde(default)]
+    pub transport: Transport,
+    /// players let in at once, more are told the server is full,
+    /// the limit of the relay if not set. While the tunnel is full the server list shows
+    /// the server as offline, its status requests are not answered
+    #[serde(default)]
+    pub max_players: Option<u16>,
+    /// fingerprint of the certificate of the relay for QUIC, the certificate of the first
+    /// connection is pinned if not set
+    #[serde(default)]
+    pub relay_fingerprint: Option<String>,
+    /// key the relay has been asked to rotate to, kept until the relay confirms the rotation
+    #[serde(default)]
+    pub pending_key: Option<ServerPrivateKey>,
+}
+
+/// how the client connects to the relay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Tcp,
+    /// every player gets a stream of its own and the tunnel survives a change of the
+    /// address of the client
+    Quic,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerAuthentication {
@@ -74,6 +141,15 @@ impl Server {
             server: format!("{}{}", id, shared::config::KEY_SERVER_SUFFIX),
             local: "25565".to_string(),
             auth: ServerAuthentication::Key(key),
+            domains: Vec::new(),
+            player_filter: None,
+            flush_delay: None,
+            compression: false,
+            connections: None,
+            max_players: None,
+            relay_fingerprint: None,
+            pending_key: None,
+            transport: Transport::default(),
         }
     }
 }
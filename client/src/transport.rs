@@ -0,0 +1,83 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use quinn::Connection;
+use shared::quic::{client_endpoint, QuicStream, SERVER_NAME};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::structs::{ClientError, Server, Transport};
+
+/// connection to the relay the packets of the tunnel are sent through
+pub enum ProxyStream {
+    Tcp(TcpStream),
+    /// the first stream of the QUIC connection
+    Quic(QuicStream),
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            ProxyStream::Quic(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ProxyStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            ProxyStream::Quic(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            ProxyStream::Quic(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            ProxyStream::Quic(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// connects to the relay with the transport of the server,
+/// returns the QUIC connection the relay opens the streams of the players on
+pub async fn connect(server: &Server) -> Result<(ProxyStream, Option<Connection>), ClientError> {
+    let addr = format!("{}:25565", &server.server);
+    match server.transport {
+        Transport::Tcp => Ok((ProxyStream::Tcp(TcpStream::connect(addr).await?), None)),
+        Transport::Quic => {
+            let relay = lookup_host(&addr).await?.next().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("could not resolve {}", addr),
+                )
+            })?;
+            let endpoint = client_endpoint(relay, server.relay_fingerprint.as_deref())?;
+            let connection = endpoint
+                .connect(relay, SERVER_NAME)
+                .map_err(|e| ClientError::Other(e.into()))?
+                .await
+                .map_err(|e| ClientError::Other(e.into()))?;
+            let stream = connection
+                .open_bi()
+                .await
+                .map_err(|e| ClientError::Other(e.into()))?;
+            Ok((ProxyStream::Quic(QuicStream::new(stream)), Some(connection)))
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use shared::addressing::DistributorError;
+use shared::crypto::ServerPublicKey;
+
+use crate::storage::{load_json, save_json};
+
+/// Public keys (base36 encoded) and tunnel hostnames
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessRules {
+    pub keys: HashSet<String>,
+    pub hostnames: HashSet<String>,
+}
+
+impl AccessRules {
+    fn contains(&self, key: &ServerPublicKey, hostnames: &[&str]) -> bool {
+        self.keys.contains(&key.to_string())
+            || self.hostnames.contains(&key.get_hostname())
+            || hostnames.iter().any(|h| self.hostnames.contains(*h))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct AccessRulesFile {
+    deny: AccessRules,
+    /// if set, only these tunnels may connect (private relay)
+    allow: Option<AccessRules>,
+}
+
+/// Decides which tunnels are allowed to connect to the relay
+#[derive(Debug, Default)]
+pub struct AccessList {
+    rules: AccessRulesFile,
+    path: Option<PathBuf>,
+    /// keys banned at runtime without a file to write them to, kept across reloads
+    runtime_bans: HashSet<String>,
+}
+
+impl AccessList {
+    pub fn load(path: Option<PathBuf>) -> Result<Self, DistributorError> {
+        let rules = load_json(path.as_deref())?;
+        Ok(Self {
+            rules,
+            path,
+            runtime_bans: HashSet::new(),
+        })
+    }
+    /// the rules are read from the new file on the next reload
+    pub fn set_path(&mut self, path: Option<PathBuf>) {
+        self.path = path;
+    }
+    /// reads the file again, keeps the old rules if the file is not valid
+    pub fn reload(&mut self) -> Result<(), DistributorError> {
+        self.rules = load_json(self.path.as_deref())?;
+        let runtime_bans = self.runtime_bans.iter().cloned();
+        self.rules.deny.keys.extend(runtime_bans);
+        Ok(())
+    }
+    /// adds the key to the deny list and writes the list back to its file,
+    /// without a file the key stays banned until the relay restarts
+    pub fn deny_key(&mut self, key: &ServerPublicKey) -> Result<(), DistributorError> {
+        self.rules.deny.keys.insert(key.to_string());
+        if self.path.is_none() {
+            tracing::warn!(
+                "no access list file is set, the ban of {} is lost on restart",
+                key
+            );
+            self.runtime_bans.insert(key.to_string());
+        }
+        save_json(self.path.as_deref(), &self.rules)
+    }
+    /// checks if the key may open a tunnel for the given hostnames
+    pub fn check(&self, key: &ServerPublicKey, hostnames: &[&str]) -> Result<(), DistributorError> {
+        if self.rules.deny.contains(key, hostnames) {
+            return Err(DistributorError::Banned);
+        }
+        match &self.rules.allow {
+            Some(allow) if !allow.contains(key, hostnames) => Err(DistributorError::NotAllowed),
+            _ => Ok(()),
+        }
+    }
+    /// returns true if a connected tunnel has to be closed because of the current rules
+    pub fn denies_hostname(&self, hostname: &str) -> bool {
+        let denied_key = |key: &String| {
+            ServerPublicKey::try_from(key.as_str())
+                .map(|key| key.get_hostname() == hostname)
+                .unwrap_or(false)
+        };
+        self.rules.deny.hostnames.contains(hostname) || self.rules.deny.keys.iter().any(denied_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_file;
+    use shared::crypto::ServerPrivateKey;
+
+    #[test]
+    fn test_deny_and_allow() {
+        let path = temp_file("access.json");
+        let banned = ServerPrivateKey::default().get_public_key();
+        let friend = ServerPrivateKey::default().get_public_key();
+        let stranger = ServerPrivateKey::default().get_public_key();
+
+        let content = serde_json::json!({
+            "deny": {
+                "keys": [banned.to_string()],
+                "hostnames": ["rotated.t.craftip.net"],
+            },
+        });
+        std::fs::write(&path, content.to_string()).unwrap();
+        let mut access = AccessList::load(Some(path.clone())).unwrap();
+        assert!(access.check(&stranger, &[]).is_ok());
+        assert!(matches!(
+            access.check(&banned, &[]),
+            Err(DistributorError::Banned)
+        ));
+        assert!(matches!(
+            access.check(&stranger, &["rotated.t.craftip.net"]),
+            Err(DistributorError::Banned)
+        ));
+        assert!(access.denies_hostname(&banned.get_hostname()));
+        assert!(!access.denies_hostname(&stranger.get_hostname()));
+
+        // private relay mode
+        let content = serde_json::json!({
+            "deny": { "keys": [banned.to_string()] },
+            "allow": { "hostnames": [friend.get_hostname()] },
+        });
+        std::fs::write(&path, content.to_string()).unwrap();
+        access.reload().unwrap();
+        assert!(access.check(&friend, &[]).is_ok());
+        assert!(matches!(
+            access.check(&stranger, &[]),
+            Err(DistributorError::NotAllowed)
+        ));
+
+        // keys banned at runtime are written to the file
+        access.deny_key(&friend).unwrap();
+        access.reload().unwrap();
+        assert!(matches!(
+            access.check(&friend, &[]),
+            Err(DistributorError::Banned)
+        ));
+        assert!(matches!(
+            access.check(&banned, &[]),
+            Err(DistributorError::Banned)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ban_without_file() {
+        let mut access = AccessList::load(None).unwrap();
+        let banned = ServerPrivateKey::default().get_public_key();
+        access.deny_key(&banned).unwrap();
+        // the ban survives a reload although it could not be written anywhere
+        access.reload().unwrap();
+        assert!(matches!(
+            access.check(&banned, &[]),
+            Err(DistributorError::Banned)
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+
+use hyper::body::Incoming;
+use hyper::header::AUTHORIZATION;
+use hyper::{Method, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use shared::addressing::DistributorError;
+use shared::admin::{AdminRequest, AdminResponse, TunnelInfo};
+use shared::crypto::ServerPublicKey;
+
+use crate::http::{response, serve, Response};
+use crate::state::RelayState;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// should be a loopback address, the api is not meant to be exposed
+    pub addr: SocketAddr,
+    /// clients have to send `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+/// Serves the admin api:
+/// - `GET /tunnels` lists the connected tunnels
+/// - `GET /tunnels/<hostname>/players` lists the players of a tunnel
+/// - `DELETE /tunnels/<hostname>` closes a tunnel
+/// - `DELETE /tunnels/<hostname>/players/<id>` kicks a player
+pub async fn serve_admin(
+    listener: TcpListener,
+    config: AdminConfig,
+    state: RelayState,
+) -> Result<(), DistributorError> {
+    serve(listener, move |request| {
+        let state = state.clone();
+        let token = config.token.clone();
+        async move {
+            if !is_authorized(&request, &token) {
+                return error(StatusCode::UNAUTHORIZED, "invalid token");
+            }
+            handle(request, state).await
+        }
+    })
+    .await
+}
+
+fn is_authorized(request: &Request<Incoming>, token: &str) -> bool {
+    let Some(header) = request.headers().get(AUTHORIZATION) else {
+        return false;
+    };
+    let expected = format!("Bearer {}", token);
+    let header = header.as_bytes();
+    // compares all bytes so the time does not depend on the position of the first mismatch
+    !token.is_empty()
+        && header.len() == expected.len()
+        && header
+            .iter()
+            .zip(expected.as_bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn handle(request: Request<Incoming>, state: RelayState) -> Response {
+    let path: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+    match (request.method(), path.as_slice()) {
+        (&Method::GET, ["tunnels"]) => {
+            let tunnels: Vec<TunnelInfo> = state
+                .tunnels()
+                .await
+                .into_iter()
+                .map(|(tunnel, _)| tunnel)
+                .collect();
+            json(StatusCode::OK, &tunnels)
+        }
+        (&Method::GET, ["tunnels", hostname, "players"]) => match state.tunnel(hostname).await {
+            Some((_, players)) => json(StatusCode::OK, &players),
+            None => error(StatusCode::NOT_FOUND, "tunnel not found"),
+        },
+        (&Method::DELETE, ["tunnels", hostname]) => {
+            if !state.close_tunnel(hostname).await {
+                return error(StatusCode::NOT_FOUND, "tunnel not found");
+            }
+            tracing::info!("tunnel {} closed by admin", hostname);
+            json(StatusCode::OK, &serde_json::json!({ "closed": hostname }))
+        }
+        (&Method::DELETE, ["tunnels", hostname, "players", id]) => {
+            let Ok(id) = id.parse() else {
+                return error(StatusCode::BAD_REQUEST, "invalid player id");
+            };
+            if !state.kick_player(hostname, id).await {
+                return error(StatusCode::NOT_FOUND, "player not found");
+            }
+            json(StatusCode::OK, &serde_json::json!({ "kicked": id }))
+        }
+        _ => error(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+/// Serves the commands of `craftip-admin` on a unix socket, only the user running
+/// the relay may connect. Requests and responses are JSON, one per line.
+#[cfg(unix)]
+pub async fn serve_admin_socket(path: &Path, state: RelayState) -> Result<(), DistributorError> {
+    use std::os::unix::fs::PermissionsExt;
+    // the socket of a previous run is still there if the relay did not exit cleanly
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let response = match serde_json::from_str(&line) {
+                    Ok(request) => handle_command(request, &state).await,
+                    Err(e) => AdminResponse::Error(format!("invalid request: {}", e)),
+                };
+                let Ok(mut response) = serde_json::to_string(&response) else {
+                    break;
+                };
+                response.push('\n');
+                if write.write_all(response.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+pub async fn handle_command(request: AdminRequest, state: &RelayState) -> AdminResponse {
+    match request {
+        AdminRequest::List => {
+            let tunnels = state.tunnels().await;
+            AdminResponse::Tunnels(tunnels.into_iter().map(|(tunnel, _)| tunnel).collect())
+        }
+        AdminRequest::Ban { key } => {
+            let Ok(key) = ServerPublicKey::try_from(key.as_str()) else {
+                return AdminResponse::Error(format!("{} is not a valid public key", key));
+            };
+            match state.ban_key(&key).await {
+                Ok(closed) => {
+                    tracing::info!("key {} banned by admin", key);
+                    AdminResponse::Banned(closed)
+                }
+                Err(e) => AdminResponse::Error(error_message(e)),
+            }
+        }
+        AdminRequest::Reload => match state.reload().await {
+            Ok(()) => AdminResponse::Reloaded,
+            Err(e) => AdminResponse::Error(error_message(e)),
+        },
+        AdminRequest::Drain => {
+            state.drain(None).await;
+            AdminResponse::Draining
+        }
+        AdminRequest::Stats => AdminResponse::Stats(state.stats()),
+    }
+}
+
+/// unknown errors only show their details in the debug output
+fn error_message(error: DistributorError) -> String {
+    match error {
+        DistributorError::UnknownError(message) => message,
+        error => error.to_string(),
+    }
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(body) => response(status, "application/json", body),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    let body = serde_json::json!({ "error": message }).to_string();
+    response(status, "application/json", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::test_state;
+    use shared::admin::{unix_timestamp, PlayerInfo};
+    use shared::socket_packet::ClientToProxy;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc;
+
+    async fn request(addr: SocketAddr, method: &str, path: &str, token: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: relay\r\nAuthorization: Bearer {}\r\nConnection: close\r\n\r\n",
+            method, path, token
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_admin_api() {
+        let state = test_state();
+        let hostname = "test.craftip.net";
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        state
+            .register
+            .lock()
+            .await
+            .servers
+            .insert(hostname.to_string(), tx);
+        // answers the queries like a connected proxy client
+        let tunnel = tokio::spawn(async move {
+            let addr: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+            let now = unix_timestamp(SystemTime::now());
+            while let Some(message) = rx.recv().await {
+                match message {
+                    ClientToProxy::Info(response) => {
+                        let info = TunnelInfo {
+                            hostname: "test.craftip.net".to_string(),
+                            addr,
+                            connected_since: now,
+                            players: 1,
+                            uploaded: 10,
+                            downloaded: 20,
+                        };
+                        let player = PlayerInfo {
+                            id: 3,
+                            addr,
+                            connected_since: now,
+                            username: Some("Notch".to_string()),
+                        };
+                        let _ = response.send((info, vec![player]));
+                    }
+                    ClientToProxy::Kick(id, response) => {
+                        let _ = response.send(id == 3);
+                    }
+                    ClientToProxy::Close => return true,
+                    _ => {}
+                }
+            }
+            false
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = AdminConfig {
+            addr,
+            token: "secret".to_string(),
+        };
+        tokio::spawn(serve_admin(listener, config, state));
+
+        let response = request(addr, "GET", "/tunnels", "wrong").await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+
+        let response = request(addr, "GET", "/tunnels", "secret").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\"hostname\":\"test.craftip.net\""));
+        assert!(response.contains("\"players\":1"));
+
+        let response = request(addr, "GET", "/tunnels/test.craftip.net/players", "secret").await;
+        assert!(response.contains("\"id\":3"));
+        assert!(response.contains("\"username\":\"Notch\""));
+        let response = request(addr, "GET", "/tunnels/other.craftip.net/players", "secret").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let path = "/tunnels/test.craftip.net/players/4";
+        assert!(request(addr, "DELETE", path, "secret")
+            .await
+            .starts_with("HTTP/1.1 404"));
+        let path = "/tunnels/test.craftip.net/players/3";
+        assert!(request(addr, "DELETE", path, "secret")
+            .await
+            .starts_with("HTTP/1.1 200"));
+
+        let response = request(addr, "DELETE", "/tunnels/test.craftip.net", "secret").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(tunnel.await.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_admin_socket() {
+        use crate::storage::tests::temp_file;
+        use shared::crypto::ServerPrivateKey;
+        use tokio::net::UnixStream;
+
+        let path = temp_file("admin.sock");
+        let state = test_state();
+        tokio::spawn({
+            let (path, state) = (path.clone(), state.clone());
+            async move { serve_admin_socket(&path, state).await }
+        });
+        let stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let mut stream = BufReader::new(stream);
+        async fn command(
+            stream: &mut BufReader<UnixStream>,
+            request: serde_json::Value,
+        ) -> AdminResponse {
+            let request = format!("{}\n", request);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        let key = ServerPrivateKey::default().get_public_key();
+        let request = serde_json::json!({ "command": "ban", "key": key.to_string() });
+        let response = command(&mut stream, request).await;
+        assert!(matches!(response, AdminResponse::Banned(closed) if closed.is_empty()));
+        assert!(state.access.lock().await.check(&key, &[]).is_err());
+
+        let response = command(
+            &mut stream,
+            serde_json::json!({ "command": "ban", "key": "invalid" }),
+        )
+        .await;
+        assert!(matches!(response, AdminResponse::Error(_)));
+
+        let response = command(&mut stream, serde_json::json!({ "command": "drain" })).await;
+        assert!(matches!(response, AdminResponse::Draining));
+        let response = command(&mut stream, serde_json::json!({ "command": "stats" })).await;
+        assert!(matches!(response, AdminResponse::Stats(stats) if stats.draining));
+        let response = command(&mut stream, serde_json::json!({ "command": "list" })).await;
+        assert!(matches!(response, AdminResponse::Tunnels(tunnels) if tunnels.is_empty()));
+        let _ = std::fs::remove_file(path);
+    }
+}
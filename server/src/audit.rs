@@ -0,0 +1,216 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use shared::addressing::DistributorError;
+use shared::admin::unix_timestamp;
+use shared::distributor_error;
+
+use crate::bandwidth::date_of;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// directory the log is written to, one file `audit-YYYY-MM-DD.jsonl` per day (UTC)
+    pub dir: PathBuf,
+    /// days the files are kept before they are deleted, kept forever if not set
+    pub retention_days: Option<u64>,
+}
+
+/// Event recorded in the audit log, used to answer abuse reports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    TunnelConnected {
+        hostname: String,
+        public_key: Option<String>,
+        addr: SocketAddr,
+    },
+    TunnelDisconnected {
+        hostname: String,
+        addr: SocketAddr,
+        uploaded: u64,
+        downloaded: u64,
+    },
+    PlayerJoined {
+        hostname: String,
+        client_id: u16,
+        addr: SocketAddr,
+        username: Option<String>,
+    },
+    PlayerLeft {
+        /// hostname the player connected to
+        hostname: String,
+        addr: SocketAddr,
+        username: Option<String>,
+        uploaded: u64,
+        downloaded: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditRecord {
+    time: u64,
+    #[serde(flatten)]
+    event: AuditEvent,
+}
+
+#[derive(Debug, Default)]
+struct AuditFile {
+    config: Option<AuditConfig>,
+    /// the file of the current day
+    current: Option<(String, File)>,
+}
+
+/// Append-only log of tunnel and player events, one JSON object per line.
+/// Failing to write is logged but never interrupts a connection.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Mutex<AuditFile>,
+}
+
+impl AuditLog {
+    pub fn new(config: Option<AuditConfig>) -> Self {
+        Self {
+            file: Mutex::new(AuditFile {
+                config,
+                current: None,
+            }),
+        }
+    }
+    /// the next event is written to the directory of the new config
+    pub fn set_config(&self, config: Option<AuditConfig>) {
+        let mut file = self.file.lock().unwrap();
+        if file.config != config {
+            *file = AuditFile {
+                config,
+                current: None,
+            };
+        }
+    }
+    pub fn record(&self, event: AuditEvent) {
+        if let Err(e) = self.write(event, SystemTime::now()) {
+            tracing::error!("could not write audit log: {:?}", e);
+        }
+    }
+    fn write(&self, event: AuditEvent, now: SystemTime) -> Result<(), DistributorError> {
+        let mut file = self.file.lock().unwrap();
+        let Some(config) = file.config.clone() else {
+            return Ok(());
+        };
+        let date = date_of(now);
+        if !matches!(&file.current, Some((current, _)) if *current == date) {
+            fs::create_dir_all(&config.dir).map_err(distributor_error!(
+                "could not create {}",
+                config.dir.display()
+            ))?;
+            let path = config.dir.join(format!("audit-{}.jsonl", date));
+            let new = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(distributor_error!("could not open {}", path.display()))?;
+            file.current = Some((date, new));
+            if let Some(days) = config.retention_days {
+                remove_expired(&config, days, now)?;
+            }
+        }
+        let record = AuditRecord {
+            time: unix_timestamp(now),
+            event,
+        };
+        let mut line = serde_json::to_string(&record)
+            .map_err(distributor_error!("could not serialize audit event"))?;
+        line.push('\n');
+        if let Some((_, current)) = &mut file.current {
+            // a single write per line, so lines of concurrent writers are never mixed
+            current
+                .write_all(line.as_bytes())
+                .map_err(distributor_error!("could not write audit event"))?;
+        }
+        Ok(())
+    }
+}
+
+/// deletes the files of the days that are older than the retention
+fn remove_expired(
+    config: &AuditConfig,
+    days: u64,
+    now: SystemTime,
+) -> Result<(), DistributorError> {
+    let oldest = now
+        .checked_sub(Duration::from_secs(days.saturating_mul(86400)))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let oldest = date_of(oldest);
+    let entries = fs::read_dir(&config.dir).map_err(distributor_error!(
+        "could not read {}",
+        config.dir.display()
+    ))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(date) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("audit-"))
+            .and_then(|name| name.strip_suffix(".jsonl"))
+        else {
+            continue;
+        };
+        // the dates sort like the days they stand for
+        if date < oldest.as_str() {
+            tracing::info!("removing expired audit log {}", entry.path().display());
+            fs::remove_file(entry.path()).map_err(distributor_error!(
+                "could not remove {}",
+                entry.path().display()
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_file;
+
+    #[test]
+    fn test_audit_log() {
+        let dir = temp_file("audit");
+        let _ = fs::remove_dir_all(&dir);
+        let log = AuditLog::new(Some(AuditConfig {
+            dir: dir.clone(),
+            retention_days: Some(2),
+        }));
+        let day = |days: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400);
+        let addr: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let joined = AuditEvent::PlayerJoined {
+            hostname: "test.craftip.net".to_string(),
+            client_id: 1,
+            addr,
+            username: None,
+        };
+        log.write(joined.clone(), day(19782)).unwrap();
+        log.write(joined.clone(), day(19782)).unwrap();
+        log.write(joined.clone(), day(19783)).unwrap();
+
+        let content = fs::read_to_string(dir.join("audit-2024-02-29.jsonl")).unwrap();
+        let records: Vec<AuditRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].time, 19782 * 86400);
+        assert_eq!(records[0].event, joined);
+        assert!(content.contains("\"event\":\"player_joined\""));
+
+        // files older than the retention are removed when a new day begins
+        log.write(joined, day(19785)).unwrap();
+        assert!(!dir.join("audit-2024-02-29.jsonl").exists());
+        assert!(dir.join("audit-2024-03-01.jsonl").exists());
+        assert!(dir.join("audit-2024-03-03.jsonl").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use shared::addressing::DistributorError;
+
+use crate::limiter::RateConfig;
+use crate::storage::{load_json, save_json};
+
+type ServerHostname = String;
+
+/// usage is written to disk at most this often
+const QUOTA_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Rates are in bytes per second. Upload is the traffic of the minecraft server
+/// to the players, download the traffic of the players to the minecraft server.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    pub tunnel_upload: Option<RateConfig>,
+    pub tunnel_download: Option<RateConfig>,
+    pub player_upload: Option<RateConfig>,
+    pub player_download: Option<RateConfig>,
+    /// bytes a tunnel may transfer per calendar month (UTC) in both directions
+    pub monthly_quota: Option<u64>,
+    /// file the traffic of the current month is persisted in
+    pub quota_file: Option<PathBuf>,
+}
+
+/// Token bucket that may go into debt, the debt has to be waited for
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    config: Option<RateConfig>,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub fn new(config: Option<RateConfig>) -> Self {
+        Self {
+            tokens: config.map(|c| c.burst).unwrap_or_default(),
+            config,
+            last: Instant::now(),
+        }
+    }
+    /// changes the rate, the debt and the saved up tokens are kept
+    pub fn set_config(&mut self, config: Option<RateConfig>) {
+        if let Some(new) = config {
+            // an unlimited throttle did not save up tokens
+            let tokens = match self.config {
+                Some(_) => self.tokens,
+                None => new.burst,
+            };
+            self.tokens = tokens.min(new.burst);
+        }
+        self.config = config;
+    }
+    /// takes the bytes from the bucket and returns how long to wait before sending them
+    pub fn delay(&mut self, bytes: usize, now: Instant) -> Duration {
+        let Some(config) = self.config else {
+            return Duration::ZERO;
+        };
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * config.per_second).min(config.burst);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 || config.per_second <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / config.per_second)
+    }
+    /// waits until the bytes may be sent
+    pub async fn throttle(&mut self, bytes: usize) {
+        let delay = self.delay(bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// returns the calendar month (UTC) of a point in time as `YYYY-MM`
+pub fn month_of(time: SystemTime) -> String {
+    date_of(time)[..7].to_string()
+}
+
+/// returns the calendar day (UTC) of a point in time as `YYYY-MM-DD`
+pub fn date_of(time: SystemTime) -> String {
+    let days = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct QuotaUsage {
+    month: String,
+    bytes: HashMap<ServerHostname, u64>,
+}
+
+/// Traffic of every tunnel in the current month
+#[derive(Debug)]
+pub struct QuotaStore {
+    usage: QuotaUsage,
+    limit: Option<u64>,
+    path: Option<PathBuf>,
+    last_save: Instant,
+}
+
+impl QuotaStore {
+    pub fn load(limit: Option<u64>, path: Option<PathBuf>) -> Result<Self, DistributorError> {
+        let usage = load_json(path.as_deref())?;
+        Ok(Self {
+            usage,
+            limit,
+            path,
+            last_save: Instant::now(),
+        })
+    }
+    pub fn set_limit(&mut self, limit: Option<u64>) {
+        self.limit = limit;
+    }
+    /// starts counting from zero when a new month begins
+    fn rollover(&mut self, now: SystemTime) {
+        let month = month_of(now);
+        if self.usage.month != month {
+            self.usage = QuotaUsage {
+                month,
+                bytes: HashMap::new(),
+            };
+        }
+    }
+    /// fails if the tunnel used up its traffic for this month
+    pub fn check(&mut self, hostname: &str, now: SystemTime) -> Result<(), DistributorError> {
+        self.rollover(now);
+        let used = self.usage.bytes.get(hostname).copied().unwrap_or_default();
+        match self.limit {
+            Some(limit) if used >= limit => Err(DistributorError::QuotaExceeded),
+            _ => Ok(()),
+        }
+    }
+    /// adds traffic of a tunnel, fails once the quota is used up
+    pub fn add(
+        &mut self,
+        hostname: &str,
+        bytes: u64,
+        now: SystemTime,
+    ) -> Result<(), DistributorError> {
+        self.rollover(now);
+        *self.usage.bytes.entry(hostname.to_string()).or_default() += bytes;
+        if self.last_save.elapsed() > QUOTA_SAVE_INTERVAL {
+            self.save()?;
+        }
+        self.check(hostname, now)
+    }
+    pub fn save(&mut self) -> Result<(), DistributorError> {
+        self.last_save = Instant::now();
+        save_json(self.path.as_deref(), &self.usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_file;
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(Some(RateConfig {
+            per_second: 1000.0,
+            burst: 1000.0,
+        }));
+        let now = Instant::now();
+        assert_eq!(throttle.delay(1000, now), Duration::ZERO);
+        assert_eq!(throttle.delay(500, now), Duration::from_millis(500));
+        // the debt has been paid after waiting
+        let later = now + Duration::from_millis(500);
+        assert_eq!(throttle.delay(0, later), Duration::ZERO);
+
+        let mut unlimited = Throttle::new(None);
+        assert_eq!(unlimited.delay(usize::MAX, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_month_of() {
+        let day = |days: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400);
+        assert_eq!(month_of(day(0)), "1970-01");
+        assert_eq!(month_of(day(31)), "1970-02");
+        // 2024-02-29 and 2024-03-01
+        assert_eq!(month_of(day(19782)), "2024-02");
+        assert_eq!(month_of(day(19783)), "2024-03");
+        assert_eq!(date_of(day(19782)), "2024-02-29");
+        assert_eq!(date_of(day(19783)), "2024-03-01");
+    }
+
+    #[test]
+    fn test_quota() {
+        let path = temp_file("quota.json");
+        let october = SystemTime::UNIX_EPOCH + Duration::from_secs(20_376 * 86400);
+        let november = october + Duration::from_secs(31 * 86400);
+        let mut quota = QuotaStore::load(Some(100), Some(path.clone())).unwrap();
+        quota.add("a", 60, october).unwrap();
+        assert!(quota.check("b", october).is_ok());
+        assert!(matches!(
+            quota.add("a", 60, october),
+            Err(DistributorError::QuotaExceeded)
+        ));
+        quota.save().unwrap();
+
+        // usage is kept across restarts and reset in the next month
+        let mut quota = QuotaStore::load(Some(100), Some(path.clone())).unwrap();
+        assert!(quota.check("a", october).is_err());
+        assert!(quota.check("a", november).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
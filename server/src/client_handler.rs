@@ -1,24 +1,49 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::watch;
+use tokio::time::timeout;
 use tokio_util::codec::Framed;
 
 use shared::addressing::{DistributorError, Tx};
+use shared::datatypes::PacketError;
 use shared::distributor_error;
-use shared::minecraft::{MinecraftDataPacket, MinecraftHelloPacket};
+use shared::minecraft::{MinecraftDataPacket, MinecraftHelloPacket, MinecraftLoginPacket};
 use shared::packet_codec::PacketCodec;
 use shared::socket_packet::{ClientToProxy, SocketPacket};
 
-#[derive(Debug)]
+use crate::audit::{AuditEvent, AuditLog};
+use crate::bandwidth::Throttle;
+use crate::config::RelayConfig;
+use crate::metrics::Metrics;
+use crate::state::RelayState;
+
+/// the Login Start packet is smaller than this, more data is not waited for
+const MAX_LOGIN_SIZE: usize = 4096;
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct MCClient {
     frames: Framed<TcpStream, PacketCodec>,
     rx: UnboundedReceiver<MinecraftDataPacket>,
     addr: SocketAddr,
     proxy_tx: Tx,
     need_for_close: bool,
+    upload: Throttle,
+    download: Throttle,
+    config: watch::Receiver<Arc<RelayConfig>>,
+    metrics: Arc<Metrics>,
+    audit: Arc<AuditLog>,
+    connected_at: Instant,
+    /// hostname the player connected to
+    hostname: String,
+    username: Option<String>,
+    uploaded: u64,
+    downloaded: u64,
 }
 
 impl MCClient {
@@ -27,37 +52,54 @@ impl MCClient {
         proxy_tx: Tx,
         frames: Framed<TcpStream, PacketCodec>,
         hello_packet: MinecraftHelloPacket,
+        login: Option<MinecraftLoginPacket>,
+        login_data: Vec<u8>,
+        state: &RelayState,
     ) -> Result<Self, DistributorError> {
         // Get the client socket address
         let addr = frames
             .get_ref()
             .peer_addr()
             .map_err(distributor_error!("could not get peer address"))?;
-        let hostname = hello_packet.hostname;
         let (tx, rx) = mpsc::unbounded_channel();
-        tracing::info!("sending client tx to proxy client {}", hostname);
+        let username = login.as_ref().map(|login| login.name.clone());
+        let legacy = hello_packet.is_legacy();
+        tracing::info!("player connected");
         proxy_tx
-            .send(ClientToProxy::AddMinecraftClient(addr, tx))
+            .send(ClientToProxy::AddMinecraftClient(addr, tx, login, legacy))
             .map_err(|_| {
                 DistributorError::UnknownError("could not add minecraft client".to_string())
             })?;
+        let mut data = hello_packet.data;
+        data.extend(login_data);
         proxy_tx
             .send(ClientToProxy::Packet(
                 addr,
-                MinecraftDataPacket {
-                    data: hello_packet.data,
-                },
+                MinecraftDataPacket { data: data.into() },
             ))
             .map_err(|_| {
                 DistributorError::UnknownError("could not add minecraft client".to_string())
             })?;
 
+        let mut config = state.subscribe_config();
+        let bandwidth = config.borrow_and_update().bandwidth.clone();
+        state.metrics.players.inc();
         Ok(MCClient {
             frames,
             rx,
             proxy_tx,
             addr,
             need_for_close: true,
+            upload: Throttle::new(bandwidth.player_upload),
+            download: Throttle::new(bandwidth.player_download),
+            config,
+            metrics: state.metrics.clone(),
+            audit: state.audit.clone(),
+            connected_at: Instant::now(),
+            hostname: hello_packet.hostname,
+            username,
+            uploaded: 0,
+            downloaded: 0,
         })
     }
     /// HANDLE MC CLIENT
@@ -67,17 +109,27 @@ impl MCClient {
                 res = self.rx.recv() => {
                     match res {
                         Some(pkg) => {
+                            self.upload.throttle(pkg.data.len()).await;
+                            self.uploaded += pkg.data.len() as u64;
                             self.frames.send(SocketPacket::from(pkg)).await.map_err(distributor_error!("could not send packet"))?;
                         }
                         None => {
                             self.need_for_close = false;
-                            tracing::info!("client channel closed by minecraft server {}", self.addr);
+                            tracing::info!("client channel closed by minecraft server");
                             break
                         }
                     }
                 }
+                // the config has been reloaded
+                Ok(()) = self.config.changed() => {
+                    let bandwidth = self.config.borrow_and_update().bandwidth.clone();
+                    self.upload.set_config(bandwidth.player_upload);
+                    self.download.set_config(bandwidth.player_download);
+                }
                 result = self.frames.next() => match result {
                     Some(Ok(SocketPacket::MCData(packet))) => {
+                        self.download.throttle(packet.data.len()).await;
+                        self.downloaded += packet.data.len() as u64;
                         if let Err(e) = self.proxy_tx.send(ClientToProxy::Packet(self.addr, packet)) {
                             tracing::error!("could not send to proxy distributor: {}", e);
                             break;
@@ -100,9 +152,52 @@ impl MCClient {
     }
 }
 
+/// Reads the Login Start packet following the handshake to learn the name of the player.
+/// Returns the data that has been read as well, it still has to be forwarded.
+pub(crate) async fn read_login(
+    frames: &mut Framed<TcpStream, PacketCodec>,
+    hello: &MinecraftHelloPacket,
+) -> (Option<MinecraftLoginPacket>, Vec<u8>) {
+    if let Some(name) = &hello.username {
+        let login = MinecraftLoginPacket {
+            name: name.clone(),
+            uuid: None,
+        };
+        return (Some(login), Vec::new());
+    }
+    // legacy pings do not send anything else until they get a response
+    if hello.is_legacy() {
+        return (None, Vec::new());
+    }
+    let mut data = Vec::new();
+    let read = async {
+        while let Some(Ok(SocketPacket::MCData(packet))) = frames.next().await {
+            data.extend_from_slice(&packet.data);
+            match MinecraftLoginPacket::new(&data, hello.version) {
+                Err(PacketError::TooSmall) if data.len() < MAX_LOGIN_SIZE => continue,
+                result => return result.ok(),
+            }
+        }
+        None
+    };
+    let login = timeout(LOGIN_TIMEOUT, read).await.ok().flatten();
+    (login, data)
+}
+
 impl Drop for MCClient {
     fn drop(&mut self) {
-        tracing::info!("dropping Client {}", self.addr);
+        tracing::info!("player disconnected");
+        self.metrics.players.dec();
+        self.metrics
+            .player_session_duration
+            .observe(self.connected_at.elapsed().as_secs_f64());
+        self.audit.record(AuditEvent::PlayerLeft {
+            hostname: self.hostname.clone(),
+            addr: self.addr,
+            username: self.username.clone(),
+            uploaded: self.uploaded,
+            downloaded: self.downloaded,
+        });
         if self.need_for_close {
             let _ = self
                 .proxy_tx
@@ -0,0 +1,213 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use shared::addressing::DistributorError;
+use shared::batching::DEFAULT_FLUSH_DELAY;
+use shared::config::MAXIMUM_CLIENTS;
+use shared::distributor_error;
+
+use crate::admin::AdminConfig;
+use crate::audit::AuditConfig;
+use crate::bandwidth::BandwidthConfig;
+use crate::limiter::LimitsConfig;
+
+/// players a tunnel may have at once if the config does not set `max_players`
+pub const DEFAULT_MAX_PLAYERS: u16 = 256;
+
+/// Configuration of the relay, loaded from a JSON file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// file the verified custom domains are persisted in
+    pub domains_file: Option<PathBuf>,
+    /// resolver used to look up the TXT records proving domain ownership
+    pub txt_resolver: TxtResolverConfig,
+    /// file the key rotations and revoked keys are persisted in
+    pub key_rotations_file: Option<PathBuf>,
+    /// file with the deny list and the optional allow list of tunnels, reloaded on SIGHUP
+    pub access_list_file: Option<PathBuf>,
+    /// connection limits per subnet and for the whole relay
+    pub limits: LimitsConfig,
+    /// bandwidth limits and traffic quotas of the tunnels
+    pub bandwidth: BandwidthConfig,
+    /// address the prometheus metrics are served on at `/metrics`, disabled if not set
+    pub metrics_addr: Option<SocketAddr>,
+    /// local api to inspect and manage the connected tunnels, disabled if not set
+    pub admin: Option<AdminConfig>,
+    /// unix socket `craftip-admin` connects to, disabled if not set
+    pub admin_socket: Option<PathBuf>,
+    /// seconds to wait for players to leave on shutdown before closing the tunnels
+    pub drain_timeout: Option<u64>,
+    /// append-only log of tunnel and player events, disabled if not set
+    pub audit: Option<AuditConfig>,
+    /// accepts clients that connect without the proxy preamble, everything that is not
+    /// a minecraft handshake is then decoded as proxy packet
+    pub legacy_proxy_clients: bool,
+    /// milliseconds the frames of the players may wait to be written to the tunnel together,
+    /// 0 writes every frame at once, 1 if not set
+    pub flush_delay: Option<u64>,
+    /// compresses the data of the tunnels whose client offers compression
+    pub compression: bool,
+    /// connections a client may open for one tunnel, 1 if not set
+    pub max_tunnel_connections: Option<u8>,
+    /// players a tunnel may have at once, [`DEFAULT_MAX_PLAYERS`] if not set, the client may
+    /// ask for fewer. Status requests of the server list count as players while they are
+    /// open, a full tunnel does not answer them, so the server list shows it as offline
+    pub max_players: Option<u16>,
+    /// UDP address the QUIC transport for clients is served on, disabled if not set
+    pub quic_addr: Option<SocketAddr>,
+    /// file the certificate of the QUIC transport is kept in, the clients pin its
+    /// fingerprint, a new certificate is generated on every start if not set
+    pub quic_certificate_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TxtResolverConfig {
+    /// query the system DNS resolver
+    #[default]
+    Dns,
+    /// read the TXT records from a JSON file mapping names to records
+    File { path: PathBuf },
+}
+
+impl RelayConfig {
+    pub fn load(path: &Path) -> Result<Self, DistributorError> {
+        let content = fs::read_to_string(path).map_err(distributor_error!(
+            "could not read config {}",
+            path.display()
+        ))?;
+        let config: Self = serde_json::from_str(&content).map_err(distributor_error!(
+            "could not parse config {}",
+            path.display()
+        ))?;
+        config.validate()?;
+        Ok(config)
+    }
+    /// rejects values that would lock everyone out or can not work
+    pub fn validate(&self) -> Result<(), DistributorError> {
+        let invalid = |message: &str| Err(DistributorError::InvalidConfig(message.to_string()));
+        let limits = &self.limits;
+        if limits.ipv4_prefix > 32 || limits.ipv6_prefix > 128 {
+            return invalid("subnet prefix is too long");
+        }
+        if limits.max_connections == 0 || limits.max_connections_per_ip == 0 {
+            return invalid("maximum connections must be at least 1");
+        }
+        for rate in [limits.minecraft, limits.proxy] {
+            if !(rate.per_second >= 0.0 && rate.burst >= 1.0) {
+                return invalid("connection rates need a burst of at least 1");
+            }
+        }
+        let bandwidth = &self.bandwidth;
+        let rates = [
+            bandwidth.tunnel_upload,
+            bandwidth.tunnel_download,
+            bandwidth.player_upload,
+            bandwidth.player_download,
+        ];
+        for rate in rates.into_iter().flatten() {
+            if !(rate.per_second > 0.0 && rate.burst > 0.0) {
+                return invalid("bandwidth limits must be positive");
+            }
+        }
+        if matches!(self.max_players, Some(max) if max == 0 || max > MAXIMUM_CLIENTS) {
+            return invalid(&format!(
+                "maximum players must be between 1 and {}",
+                MAXIMUM_CLIENTS
+            ));
+        }
+        if matches!(&self.admin, Some(admin) if admin.token.is_empty()) {
+            return invalid("admin token must not be empty");
+        }
+        Ok(())
+    }
+    pub fn flush_delay(&self) -> Duration {
+        self.flush_delay
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_FLUSH_DELAY)
+    }
+    pub fn max_tunnel_connections(&self) -> u8 {
+        self.max_tunnel_connections.unwrap_or(1).max(1)
+    }
+    pub fn max_players(&self) -> u16 {
+        self.max_players
+            .unwrap_or(DEFAULT_MAX_PLAYERS)
+            .clamp(1, MAXIMUM_CLIENTS)
+    }
+    /// returns the settings that only take effect after a restart and differ in the new config
+    pub fn restart_required(&self, new: &RelayConfig) -> Vec<&'static str> {
+        fn changed<T: Serialize>(old: &T, new: &T) -> bool {
+            serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+        }
+        let mut fields = Vec::new();
+        if changed(&self.domains_file, &new.domains_file) {
+            fields.push("domains_file");
+        }
+        if changed(&self.txt_resolver, &new.txt_resolver) {
+            fields.push("txt_resolver");
+        }
+        if changed(&self.key_rotations_file, &new.key_rotations_file) {
+            fields.push("key_rotations_file");
+        }
+        if self.limits.max_connections != new.limits.max_connections {
+            fields.push("limits.max_connections");
+        }
+        if changed(&self.bandwidth.quota_file, &new.bandwidth.quota_file) {
+            fields.push("bandwidth.quota_file");
+        }
+        if changed(&self.quic_addr, &new.quic_addr) {
+            fields.push("quic_addr");
+        }
+        if changed(&self.quic_certificate_file, &new.quic_certificate_file) {
+            fields.push("quic_certificate_file");
+        }
+        if changed(&self.metrics_addr, &new.metrics_addr) {
+            fields.push("metrics_addr");
+        }
+        if changed(&self.admin, &new.admin) {
+            fields.push("admin");
+        }
+        if changed(&self.admin_socket, &new.admin_socket) {
+            fields.push("admin_socket");
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limiter::RateConfig;
+
+    #[test]
+    fn test_validate() {
+        assert!(RelayConfig::default().validate().is_ok());
+        let mut config = RelayConfig::default();
+        config.limits.ipv4_prefix = 33;
+        assert!(config.validate().is_err());
+        let mut config = RelayConfig::default();
+        config.bandwidth.player_upload = Some(RateConfig {
+            per_second: 0.0,
+            burst: 1000.0,
+        });
+        assert!(config.validate().is_err());
+        let config = RelayConfig {
+            max_players: Some(MAXIMUM_CLIENTS + 1),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let mut new = RelayConfig::default();
+        new.limits.max_connections_per_ip = 1;
+        new.metrics_addr = Some("127.0.0.1:9100".parse().unwrap());
+        assert_eq!(
+            RelayConfig::default().restart_required(&new),
+            ["metrics_addr"]
+        );
+    }
+}
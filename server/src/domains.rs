@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hickory_resolver::TokioAsyncResolver;
+
+use shared::addressing::DistributorError;
+use shared::config::{DOMAIN_CHALLENGE_SUBDOMAIN, KEY_SERVER_SUFFIX};
+use shared::crypto::ServerPublicKey;
+use shared::distributor_error;
+
+use crate::config::TxtResolverConfig;
+use crate::storage::{load_json, save_json};
+
+type Domain = String;
+type ServerHostname = String;
+
+/// Looks up the TXT records of a name, used to verify the ownership of custom domains
+pub trait TxtResolver: Send + Sync {
+    fn lookup_txt<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, DistributorError>>;
+}
+
+/// Resolves TXT records using the DNS configuration of the system
+pub struct DnsTxtResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsTxtResolver {
+    pub fn new() -> Result<Self, DistributorError> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(distributor_error!("could not create dns resolver"))?;
+        Ok(Self { resolver })
+    }
+}
+
+impl TxtResolver for DnsTxtResolver {
+    fn lookup_txt<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, DistributorError>> {
+        async move {
+            let lookup = self
+                .resolver
+                .txt_lookup(name)
+                .await
+                .map_err(distributor_error!(
+                    "could not look up TXT record of {}",
+                    name
+                ))?;
+            Ok(lookup.iter().map(|txt| txt.to_string()).collect())
+        }
+        .boxed()
+    }
+}
+
+/// Reads TXT records from a JSON file of the form `{"name": ["record", ...]}`
+/// the file is read on every lookup so it can be edited while the relay is running
+pub struct FileTxtResolver {
+    path: PathBuf,
+}
+
+impl FileTxtResolver {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl TxtResolver for FileTxtResolver {
+    fn lookup_txt<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, DistributorError>> {
+        async move {
+            let content = tokio::fs::read_to_string(&self.path)
+                .await
+                .map_err(distributor_error!("could not read {}", self.path.display()))?;
+            let mut records: HashMap<String, Vec<String>> = serde_json::from_str(&content)
+                .map_err(distributor_error!(
+                    "could not parse {}",
+                    self.path.display()
+                ))?;
+            Ok(records.remove(name).unwrap_or_default())
+        }
+        .boxed()
+    }
+}
+
+pub fn create_resolver(
+    config: &TxtResolverConfig,
+) -> Result<Arc<dyn TxtResolver>, DistributorError> {
+    Ok(match config {
+        TxtResolverConfig::Dns => Arc::new(DnsTxtResolver::new()?),
+        TxtResolverConfig::File { path } => Arc::new(FileTxtResolver::new(path.clone())),
+    })
+}
+
+/// Brings a hostname sent by a minecraft client into a comparable form.
+/// Strips the forge marker (`\0FML\0`), a trailing dot and lowercases it.
+pub fn normalize_hostname(hostname: &str) -> String {
+    let hostname = hostname.split('\0').next().unwrap_or_default();
+    hostname.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Checks that the TXT record `_craftip.<domain>` contains the challenge of the key.
+/// Returns the normalized domain if the ownership has been proven.
+pub async fn verify_ownership(
+    resolver: &dyn TxtResolver,
+    domain: &str,
+    public_key: &ServerPublicKey,
+) -> Result<Domain, DistributorError> {
+    let domain = normalize_hostname(domain);
+    // domains of the relay itself can not be claimed
+    if domain.is_empty()
+        || domain.ends_with(KEY_SERVER_SUFFIX)
+        || domain == KEY_SERVER_SUFFIX.trim_start_matches('.')
+    {
+        return Err(DistributorError::DomainNotVerified(domain));
+    }
+    let challenge = public_key.get_domain_challenge(&domain);
+    let name = format!("{}.{}", DOMAIN_CHALLENGE_SUBDOMAIN, domain);
+    let records = resolver.lookup_txt(&name).await?;
+    if !records.iter().any(|record| record.trim() == challenge) {
+        tracing::info!("TXT record {} does not contain {}", name, challenge);
+        return Err(DistributorError::DomainNotVerified(domain));
+    }
+    Ok(domain)
+}
+
+/// Custom domains that have been verified and the tunnel hostname they point to
+#[derive(Debug, Default)]
+pub struct DomainStore {
+    domains: HashMap<Domain, ServerHostname>,
+    path: Option<PathBuf>,
+}
+
+impl DomainStore {
+    /// loads the verified domains from `path` if it exists
+    pub fn load(path: Option<PathBuf>) -> Result<Self, DistributorError> {
+        let domains = load_json(path.as_deref())?;
+        Ok(Self { domains, path })
+    }
+    /// returns the tunnel hostname a custom domain is routed to
+    pub fn get(&self, hostname: &str) -> Option<&ServerHostname> {
+        self.domains.get(&normalize_hostname(hostname))
+    }
+    /// routes a verified domain to a tunnel hostname and persists the change
+    pub fn insert(
+        &mut self,
+        domain: Domain,
+        hostname: ServerHostname,
+    ) -> Result<(), DistributorError> {
+        self.domains.insert(domain, hostname);
+        self.save()
+    }
+    fn save(&self) -> Result<(), DistributorError> {
+        save_json(self.path.as_deref(), &self.domains)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_file;
+    use shared::crypto::ServerPrivateKey;
+    use std::fs;
+
+    #[test]
+    fn test_normalize_hostname() {
+        assert_eq!(normalize_hostname("MC.Example.org."), "mc.example.org");
+        assert_eq!(
+            normalize_hostname("mc.example.org\0FML2\0"),
+            "mc.example.org"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_domain() {
+        let records = temp_file("records.json");
+        let public_key = ServerPrivateKey::default().get_public_key();
+        let challenge = public_key.get_domain_challenge("mc.example.org");
+        let resolver = FileTxtResolver::new(records.clone());
+
+        fs::write(
+            &records,
+            r#"{"_craftip.mc.example.org": ["something else"]}"#,
+        )
+        .unwrap();
+        assert!(verify_ownership(&resolver, "mc.example.org", &public_key)
+            .await
+            .is_err());
+
+        let content = serde_json::json!({ "_craftip.mc.example.org": [challenge] });
+        fs::write(&records, content.to_string()).unwrap();
+        let domain = verify_ownership(&resolver, "MC.example.org.", &public_key)
+            .await
+            .unwrap();
+        assert_eq!(domain, "mc.example.org");
+
+        // the key of somebody else does not match the challenge
+        let other = ServerPrivateKey::default().get_public_key();
+        assert!(verify_ownership(&resolver, "mc.example.org", &other)
+            .await
+            .is_err());
+        fs::remove_file(records).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_relay_domains_cannot_be_claimed() {
+        let records = temp_file("records-suffix.json");
+        let public_key = ServerPrivateKey::default().get_public_key();
+        let domain = format!("abc{}", KEY_SERVER_SUFFIX);
+        let name = format!("_craftip.{}", domain);
+        let content = serde_json::json!({ name: [public_key.get_domain_challenge(&domain)] });
+        fs::write(&records, content.to_string()).unwrap();
+
+        let resolver = FileTxtResolver::new(records.clone());
+        assert!(verify_ownership(&resolver, &domain, &public_key)
+            .await
+            .is_err());
+        fs::remove_file(records).unwrap();
+    }
+
+    #[test]
+    fn test_domain_store_persistence() {
+        let path = temp_file("domains.json");
+        let mut domains = DomainStore::load(Some(path.clone())).unwrap();
+        domains
+            .insert(
+                "mc.example.org".to_string(),
+                "abc.t.craftip.net".to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            domains.get("MC.example.org."),
+            Some(&"abc.t.craftip.net".to_string())
+        );
+
+        let domains = DomainStore::load(Some(path.clone())).unwrap();
+        assert_eq!(
+            domains.get("mc.example.org"),
+            Some(&"abc.t.craftip.net".to_string())
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
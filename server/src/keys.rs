@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use shared::addressing::DistributorError;
+use shared::crypto::{KeyRotation, ServerPublicKey};
+
+use crate::storage::{load_json, save_json};
+
+type ServerHostname = String;
+
+/// aliases are followed at most this often, protects against broken files
+const MAXIMUM_ALIAS_DEPTH: usize = 16;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct KeyRotations {
+    /// hostname of a rotated key -> hostname of the key it was rotated to
+    aliases: HashMap<ServerHostname, ServerHostname>,
+    /// keys that have been rotated, encoded as base36 strings
+    revoked: HashSet<String>,
+}
+
+/// Persists key rotations so the hostname of an old key keeps routing to the new key
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    rotations: KeyRotations,
+    path: Option<PathBuf>,
+}
+
+impl KeyStore {
+    pub fn load(path: Option<PathBuf>) -> Result<Self, DistributorError> {
+        let rotations = load_json(path.as_deref())?;
+        Ok(Self { rotations, path })
+    }
+    pub fn is_revoked(&self, key: &ServerPublicKey) -> bool {
+        self.rotations.revoked.contains(&key.to_string())
+    }
+    /// follows the rotations of a hostname to the hostname of the currently valid key
+    pub fn resolve(&self, hostname: &str) -> ServerHostname {
+        let mut hostname = hostname;
+        for _ in 0..MAXIMUM_ALIAS_DEPTH {
+            match self.rotations.aliases.get(hostname) {
+                Some(alias) => hostname = alias,
+                None => break,
+            }
+        }
+        hostname.to_string()
+    }
+    /// verifies the rotation, revokes the old key and routes its hostname to the new key
+    pub fn rotate(&mut self, rotation: &KeyRotation) -> Result<ServerHostname, DistributorError> {
+        if !rotation.verify() {
+            return Err(DistributorError::InvalidKeyRotation);
+        }
+        if self.is_revoked(&rotation.old_key) || self.is_revoked(&rotation.new_key) {
+            return Err(DistributorError::KeyRevoked);
+        }
+        let old_hostname = rotation.old_key.get_hostname();
+        let new_hostname = rotation.new_key.get_hostname();
+        self.rotations
+            .aliases
+            .insert(old_hostname, new_hostname.clone());
+        self.rotations.revoked.insert(rotation.old_key.to_string());
+        save_json(self.path.as_deref(), &self.rotations)?;
+        Ok(new_hostname)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_file;
+    use shared::crypto::ServerPrivateKey;
+
+    #[test]
+    fn test_rotation() {
+        let path = temp_file("keys.json");
+        let first = ServerPrivateKey::default();
+        let second = ServerPrivateKey::default();
+        let third = ServerPrivateKey::default();
+        let first_hostname = first.get_public_key().get_hostname();
+        let third_hostname = third.get_public_key().get_hostname();
+
+        let mut keys = KeyStore::load(Some(path.clone())).unwrap();
+        keys.rotate(&first.rotate_to(&second.get_public_key()))
+            .unwrap();
+        keys.rotate(&second.rotate_to(&third.get_public_key()))
+            .unwrap();
+        assert!(keys.is_revoked(&first.get_public_key()));
+        assert!(keys.is_revoked(&second.get_public_key()));
+        assert!(!keys.is_revoked(&third.get_public_key()));
+        assert_eq!(keys.resolve(&first_hostname), third_hostname);
+
+        // revoked keys can neither rotate again nor be rotated to
+        assert!(keys
+            .rotate(&first.rotate_to(&ServerPrivateKey::default().get_public_key()))
+            .is_err());
+        assert!(keys
+            .rotate(&third.rotate_to(&first.get_public_key()))
+            .is_err());
+
+        let keys = KeyStore::load(Some(path.clone())).unwrap();
+        assert_eq!(keys.resolve(&first_hostname), third_hostname);
+        assert!(keys.is_revoked(&first.get_public_key()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_forged_rotation() {
+        let mut keys = KeyStore::default();
+        let old = ServerPrivateKey::default();
+        let attacker = ServerPrivateKey::default();
+        let mut rotation = attacker.rotate_to(&attacker.get_public_key());
+        rotation.old_key = old.get_public_key();
+        assert!(keys.rotate(&rotation).is_err());
+        assert!(!keys.is_revoked(&old.get_public_key()));
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use prometheus::{IntCounterVec, Opts};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// buckets are cleaned up once there are more than this many
+const MAXIMUM_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// connections that are handled at the same time by the relay
+    pub max_connections: usize,
+    /// connections that are handled at the same time per subnet
+    pub max_connections_per_ip: usize,
+    /// rate of new minecraft connections per subnet
+    pub minecraft: RateConfig,
+    /// rate of proxy handshakes per subnet
+    pub proxy: RateConfig,
+    /// IPv4 addresses are grouped into subnets of this prefix length
+    pub ipv4_prefix: u8,
+    /// IPv6 addresses are grouped into subnets of this prefix length
+    pub ipv6_prefix: u8,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10_000,
+            max_connections_per_ip: 100,
+            minecraft: RateConfig {
+                per_second: 5.0,
+                burst: 20.0,
+            },
+            proxy: RateConfig {
+                per_second: 0.5,
+                burst: 5.0,
+            },
+            ipv4_prefix: 32,
+            ipv6_prefix: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateConfig {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(config: &RateConfig, now: Instant) -> Self {
+        Self {
+            tokens: config.burst,
+            last: now,
+        }
+    }
+    fn refill(&mut self, config: &RateConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.per_second).min(config.burst);
+        self.last = now;
+    }
+    /// takes `amount` tokens if available
+    pub fn try_take(&mut self, config: &RateConfig, amount: f64, now: Instant) -> bool {
+        self.refill(config, now);
+        if self.tokens < amount {
+            return false;
+        }
+        self.tokens -= amount;
+        true
+    }
+    fn is_full(&mut self, config: &RateConfig, now: Instant) -> bool {
+        self.refill(config, now);
+        self.tokens >= config.burst
+    }
+}
+
+/// groups addresses into subnets so a client can not bypass limits by using many addresses
+pub fn subnet(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX
+                .checked_shl(32 - ipv4_prefix.min(32) as u32)
+                .unwrap_or(0);
+            IpAddr::from((u32::from(ip) & mask).to_be_bytes())
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return subnet(IpAddr::V4(ip), ipv4_prefix, ipv6_prefix);
+            }
+            let mask = u128::MAX
+                .checked_shl(128 - ipv6_prefix.min(128) as u32)
+                .unwrap_or(0);
+            IpAddr::from((u128::from(ip) & mask).to_be_bytes())
+        }
+    }
+}
+
+/// Token bucket per subnet
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateConfig,
+    buckets: HashMap<IpAddr, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+    pub fn check(&mut self, subnet: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() > MAXIMUM_BUCKETS {
+            // full buckets behave exactly like new ones
+            let config = self.config;
+            self.buckets
+                .retain(|_, bucket| !bucket.is_full(&config, now));
+        }
+        let config = &self.config;
+        self.buckets
+            .entry(subnet)
+            .or_insert_with(|| TokenBucket::new(config, now))
+            .try_take(config, 1.0, now)
+    }
+    /// existing buckets keep their tokens, they are capped at the new burst size on refill
+    pub fn set_config(&mut self, config: RateConfig) {
+        self.config = config;
+    }
+}
+
+/// Counts the connections the relay refused
+#[derive(Debug, Clone)]
+pub struct RejectedConnections {
+    pub counter: IntCounterVec,
+}
+
+impl Default for RejectedConnections {
+    fn default() -> Self {
+        let opts = Opts::new(
+            "craftip_rejected_connections_total",
+            "connections refused by the limiter",
+        );
+        Self {
+            counter: IntCounterVec::new(opts, &["reason"]).expect("valid metric"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rejection {
+    MaxConnections,
+    MaxConnectionsPerIp,
+    MinecraftRate,
+    ProxyRate,
+}
+
+impl Rejection {
+    const ALL: [Rejection; 4] = [
+        Rejection::MaxConnections,
+        Rejection::MaxConnectionsPerIp,
+        Rejection::MinecraftRate,
+        Rejection::ProxyRate,
+    ];
+    fn label(&self) -> &'static str {
+        match self {
+            Rejection::MaxConnections => "max_connections",
+            Rejection::MaxConnectionsPerIp => "max_connections_per_ip",
+            Rejection::MinecraftRate => "minecraft_rate",
+            Rejection::ProxyRate => "proxy_rate",
+        }
+    }
+}
+
+impl RejectedConnections {
+    pub fn count(&self, rejection: Rejection) {
+        self.counter.with_label_values(&[rejection.label()]).inc();
+    }
+    /// number of refused connections by reason
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        Rejection::ALL
+            .iter()
+            .map(|rejection| {
+                let count = self.counter.with_label_values(&[rejection.label()]).get();
+                (rejection.label().to_string(), count)
+            })
+            .collect()
+    }
+}
+
+/// Keeps a connection slot reserved until the connection is dropped
+pub struct ConnectionPermit {
+    _permit: OwnedSemaphorePermit,
+    subnet: IpAddr,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.subnet) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.subnet);
+            }
+        }
+    }
+}
+
+/// Limits the rate and the number of connections per subnet
+pub struct Limiter {
+    config: Mutex<LimitsConfig>,
+    semaphore: Arc<Semaphore>,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    minecraft: Mutex<RateLimiter>,
+    proxy: Mutex<RateLimiter>,
+    pub rejected: RejectedConnections,
+}
+
+impl Limiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(config.max_connections)),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            minecraft: Mutex::new(RateLimiter::new(config.minecraft)),
+            proxy: Mutex::new(RateLimiter::new(config.proxy)),
+            rejected: RejectedConnections::default(),
+            config: Mutex::new(config),
+        }
+    }
+    /// applies new limits, the maximum number of connections can only be changed by a restart
+    pub fn update(&self, config: LimitsConfig) {
+        self.minecraft.lock().unwrap().set_config(config.minecraft);
+        self.proxy.lock().unwrap().set_config(config.proxy);
+        *self.config.lock().unwrap() = config;
+    }
+    fn subnet(&self, ip: IpAddr) -> IpAddr {
+        let config = self.config.lock().unwrap();
+        subnet(ip, config.ipv4_prefix, config.ipv6_prefix)
+    }
+    /// reserves a slot for a new connection, fails if the relay or the subnet is full
+    pub fn accept(&self, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        let permit = self
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| self.reject(Rejection::MaxConnections))?;
+        let subnet = self.subnet(ip);
+        let max_connections_per_ip = self.config.lock().unwrap().max_connections_per_ip;
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(subnet).or_default();
+        if *count >= max_connections_per_ip {
+            return Err(self.reject(Rejection::MaxConnectionsPerIp));
+        }
+        *count += 1;
+        Ok(ConnectionPermit {
+            _permit: permit,
+            subnet,
+            connections: self.connections.clone(),
+        })
+    }
+    pub fn check_minecraft(&self, ip: IpAddr) -> Result<(), Rejection> {
+        let subnet = self.subnet(ip);
+        match self.minecraft.lock().unwrap().check(subnet, Instant::now()) {
+            true => Ok(()),
+            false => Err(self.reject(Rejection::MinecraftRate)),
+        }
+    }
+    pub fn check_proxy(&self, ip: IpAddr) -> Result<(), Rejection> {
+        let subnet = self.subnet(ip);
+        match self.proxy.lock().unwrap().check(subnet, Instant::now()) {
+            true => Ok(()),
+            false => Err(self.reject(Rejection::ProxyRate)),
+        }
+    }
+    fn reject(&self, rejection: Rejection) -> Rejection {
+        tracing::debug!("rejected connection: {:?}", rejection);
+        self.rejected.count(rejection);
+        rejection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        let config = RateConfig {
+            per_second: 2.0,
+            burst: 3.0,
+        };
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(&config, now);
+        assert!(bucket.try_take(&config, 1.0, now));
+        assert!(bucket.try_take(&config, 1.0, now));
+        assert!(bucket.try_take(&config, 1.0, now));
+        assert!(!bucket.try_take(&config, 1.0, now));
+        let later = now + Duration::from_millis(500);
+        assert!(bucket.try_take(&config, 1.0, later));
+        assert!(!bucket.try_take(&config, 1.0, later));
+        // never refills above the burst size
+        let much_later = now + Duration::from_secs(60);
+        assert!(bucket.try_take(&config, 3.0, much_later));
+        assert!(!bucket.try_take(&config, 1.0, much_later));
+    }
+
+    #[test]
+    fn test_subnet() {
+        let ip: IpAddr = "192.168.12.34".parse().unwrap();
+        assert_eq!(
+            subnet(ip, 24, 64),
+            "192.168.12.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(subnet(ip, 32, 64), ip);
+        assert_eq!(subnet(ip, 0, 64), "0.0.0.0".parse::<IpAddr>().unwrap());
+        let ip: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+        assert_eq!(
+            subnet(ip, 32, 64),
+            "2001:db8:1:2::".parse::<IpAddr>().unwrap()
+        );
+        let ip: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+        assert_eq!(subnet(ip, 8, 64), "10.0.0.0".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_rate_limiter_per_subnet() {
+        let mut limiter = RateLimiter::new(RateConfig {
+            per_second: 1.0,
+            burst: 1.0,
+        });
+        let now = Instant::now();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(limiter.check(a, now));
+        assert!(!limiter.check(a, now));
+        assert!(limiter.check(b, now));
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let limiter = Limiter::new(LimitsConfig {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            ..Default::default()
+        });
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let first = limiter.accept(a).unwrap();
+        let _second = limiter.accept(a).unwrap();
+        assert!(matches!(
+            limiter.accept(a),
+            Err(Rejection::MaxConnectionsPerIp)
+        ));
+        let _third = limiter.accept(b).unwrap();
+        assert!(matches!(limiter.accept(b), Err(Rejection::MaxConnections)));
+        drop(first);
+        assert!(limiter.accept(a).is_ok());
+        let rejected = |rejection: Rejection| {
+            let counter = &limiter.rejected.counter;
+            counter.with_label_values(&[rejection.label()]).get()
+        };
+        assert_eq!(rejected(Rejection::MaxConnections), 1);
+        assert_eq!(rejected(Rejection::MaxConnectionsPerIp), 1);
+    }
+
+    #[test]
+    fn test_update_limits() {
+        let limiter = Limiter::new(LimitsConfig {
+            max_connections_per_ip: 1,
+            ..Default::default()
+        });
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let _first = limiter.accept(a).unwrap();
+        assert!(limiter.accept(a).is_err());
+        limiter.update(LimitsConfig {
+            max_connections_per_ip: 2,
+            ..Default::default()
+        });
+        assert!(limiter.accept(a).is_ok());
+    }
+}
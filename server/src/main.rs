@@ -1,49 +1,247 @@
 use std::env;
 use std::error::Error;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+use tracing::field::Empty;
+use tracing::Instrument;
 
+use crate::admin::serve_admin;
+use crate::config::RelayConfig;
+use crate::metrics::serve_metrics;
 use crate::process_socket::process_socket_connection;
-use shared::addressing::{DistributorError, Register};
+use crate::quic::{load_certificate, serve_quic};
+use crate::state::RelayState;
+use shared::addressing::DistributorError;
+use shared::logging::init_logging;
 
+mod access;
+mod admin;
+mod audit;
+mod bandwidth;
 mod client_handler;
+mod config;
+mod domains;
+mod http;
+mod keys;
+mod limiter;
+mod metrics;
 mod process_socket;
 mod proxy_handler;
+mod quic;
+mod state;
+mod storage;
+
+/// seconds to wait for players to leave on shutdown if not configured
+const DEFAULT_DRAIN_TIMEOUT: u64 = 60;
+/// the config file is checked for changes this often
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// time the tunnels get to close their connections after the drain timeout
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(false)
-        .with_target(false)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber)?;
+    let _telemetry = init_logging("craftip-relay", "info")?;
 
     let addr = env::args()
         .nth(1)
         .unwrap_or_else(|| "127.0.0.1:25565".to_string());
+    let config_path = env::args().nth(2).map(PathBuf::from);
+    let config = match &config_path {
+        Some(path) => RelayConfig::load(path)?,
+        None => RelayConfig::default(),
+    };
 
     let mc_listener = TcpListener::bind(&addr).await?;
     tracing::info!("server running on {:?}", mc_listener.local_addr()?);
-    let register = Arc::new(Mutex::new(Register::new()));
-    loop {
-        let (socket, _addr) = mc_listener.accept().await?;
-        let register = Arc::clone(&register);
+    let state = RelayState::new(config, config_path.clone())?;
+    let config = state.config();
+    if let Some(quic_addr) = config.quic_addr {
+        let certificate = load_certificate(config.quic_certificate_file.as_deref())?;
+        let endpoint = shared::quic::server_endpoint(quic_addr, &certificate)?;
+        tracing::info!(
+            fingerprint = certificate.fingerprint()?,
+            "quic transport served on udp {}",
+            endpoint.local_addr()?
+        );
+        tokio::spawn(serve_quic(endpoint, state.clone()));
+    }
+    if let Some(metrics_addr) = config.metrics_addr {
+        let listener = TcpListener::bind(metrics_addr).await?;
+        tracing::info!("metrics served on http://{}/metrics", metrics_addr);
+        let metrics = state.metrics.clone();
         tokio::spawn(async move {
-            match process_socket_connection(socket, register).await {
-                Ok(_) => tracing::info!("client disconnected"),
-                Err(DistributorError::UnknownError(err)) => {
-                    tracing::error!("client error: {}", err)
-                }
-                Err(e) => {
-                    tracing::info!("client error: {:?}", e);
-                }
+            if let Err(e) = serve_metrics(listener, metrics).await {
+                tracing::error!("metrics endpoint failed: {}", e);
+            }
+        });
+    }
+    if let Some(admin) = config.admin.clone() {
+        if !admin.addr.ip().is_loopback() {
+            tracing::warn!("admin api is reachable from other hosts on {}", admin.addr);
+        }
+        let listener = TcpListener::bind(admin.addr).await?;
+        tracing::info!("admin api served on http://{}", admin.addr);
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_admin(listener, admin, state).await {
+                tracing::error!("admin api failed: {}", e);
+            }
+        });
+    }
+    #[cfg(unix)]
+    if let Some(path) = config.admin_socket.clone() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            tracing::info!("admin socket listening on {}", path.display());
+            if let Err(e) = admin::serve_admin_socket(&path, state).await {
+                tracing::error!("admin socket failed: {}", e);
             }
         });
     }
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone()));
+    if let Some(path) = config_path {
+        tokio::spawn(watch_config_file(state.clone(), path));
+    }
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let (socket, addr) = tokio::select! {
+            result = mc_listener.accept() => result?,
+            _ = &mut shutdown => break,
+        };
+        let permit = match state.limiter.accept(addr.ip()) {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                tracing::debug!("refusing connection from {}: {:?}", addr, rejection);
+                continue;
+            }
+        };
+        let state = state.clone();
+        // the hostname is recorded as soon as the first packet has been read
+        let span =
+            tracing::info_span!("connection", peer = %addr, protocol = Empty, hostname = Empty);
+        tokio::spawn(
+            async move {
+                // the slot is released when the connection is done
+                let _permit = permit;
+                match process_socket_connection(socket, state).await {
+                    Ok(_) => tracing::info!("client disconnected"),
+                    Err(DistributorError::UnknownError(err)) => {
+                        tracing::error!(error = %err, "client error")
+                    }
+                    Err(e) => {
+                        tracing::info!(error = ?e, "client error");
+                    }
+                }
+            }
+            .instrument(span),
+        );
+    }
+    drop(mc_listener);
+    shutdown_gracefully(&state).await;
+    #[cfg(unix)]
+    if let Some(path) = &config.admin_socket {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => {},
+                _ = tokio::signal::ctrl_c() => {},
+            },
+            Err(e) => {
+                tracing::error!("could not listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// tells the tunnels that the relay is shutting down, waits for the players to leave
+/// until the drain timeout and closes the remaining sessions
+async fn shutdown_gracefully(state: &RelayState) {
+    let drain_timeout = state
+        .config()
+        .drain_timeout
+        .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+    let drain_timeout = Duration::from_secs(drain_timeout);
+    tracing::info!(
+        "shutting down, waiting up to {:?} for players to leave",
+        drain_timeout
+    );
+    state.drain(Some(drain_timeout)).await;
+    let players_left = async {
+        while state.metrics.players.get() > 0 {
+            sleep(Duration::from_millis(500)).await;
+        }
+    };
+    tokio::select! {
+        _ = timeout(drain_timeout, players_left) => {},
+        _ = shutdown_signal() => tracing::info!("received second signal, not waiting for players"),
+    }
+    tracing::info!(
+        "closing {} tunnels with {} players",
+        state.metrics.active_tunnels.get(),
+        state.metrics.players.get()
+    );
+    state.close_all_tunnels().await;
+    // the tunnels save their traffic quota when they are closed
+    let tunnels_closed = async {
+        while state.metrics.active_tunnels.get() > 0 {
+            sleep(Duration::from_millis(100)).await;
+        }
+    };
+    if timeout(CLOSE_TIMEOUT, tunnels_closed).await.is_err() {
+        tracing::warn!("not all tunnels closed in time");
+    }
+}
+
+/// reloads the config every time the file is modified
+async fn watch_config_file(state: RelayState, path: PathBuf) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified: Option<SystemTime> = modified(&path);
+    loop {
+        sleep(CONFIG_POLL_INTERVAL).await;
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        match state.reload().await {
+            Ok(()) => tracing::info!("config {} reloaded", path.display()),
+            Err(e) => tracing::error!("could not reload config, keeping the old one: {:?}", e),
+        }
+    }
+}
+
+/// reloads the config and the access list every time the process receives SIGHUP
+#[cfg(unix)]
+async fn reload_on_sighup(state: RelayState) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!("could not listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match state.reload().await {
+            Ok(()) => tracing::info!("config reloaded"),
+            Err(e) => tracing::error!("could not reload config, keeping the old one: {:?}", e),
+        }
+    }
 }
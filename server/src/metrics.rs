@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use hyper::{Method, StatusCode};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use tokio::net::TcpListener;
+
+use shared::addressing::DistributorError;
+use shared::datatypes::PacketError;
+use shared::distributor_error;
+use shared::packet_codec::PacketCodecError;
+
+use crate::http::{response, serve};
+
+/// Metrics of the relay. Labels only take a fixed set of values, hostnames and
+/// addresses are never used as labels.
+pub struct Metrics {
+    registry: Registry,
+    /// connections by the protocol of their first packet
+    pub connections: IntCounterVec,
+    /// connections that did not send a valid first packet
+    pub handshake_errors: IntCounterVec,
+    pub auth_failures: IntCounterVec,
+    pub auth_duration: Histogram,
+    pub active_tunnels: IntGauge,
+    pub players: IntGauge,
+    /// players on a tunnel, observed every time a player joins
+    pub tunnel_players: Histogram,
+    /// payload forwarded between players and tunnels by direction
+    pub forwarded_bytes: IntCounterVec,
+    pub player_session_duration: Histogram,
+}
+
+impl Metrics {
+    /// creates the metrics, additional collectors like the limiter are registered as well
+    pub fn new(collectors: Vec<Box<dyn Collector>>) -> Result<Self, DistributorError> {
+        let registry = Registry::new();
+        let metrics = Self {
+            connections: IntCounterVec::new(
+                Opts::new("craftip_connections_total", "accepted connections"),
+                &["protocol"],
+            )
+            .map_err(distributor_error!("could not create metric"))?,
+            handshake_errors: IntCounterVec::new(
+                Opts::new(
+                    "craftip_handshake_errors_total",
+                    "connections without a valid first packet",
+                ),
+                &["error"],
+            )
+            .map_err(distributor_error!("could not create metric"))?,
+            auth_failures: IntCounterVec::new(
+                Opts::new(
+                    "craftip_auth_failures_total",
+                    "failed authentications of proxy clients",
+                ),
+                &["reason"],
+            )
+            .map_err(distributor_error!("could not create metric"))?,
+            auth_duration: Histogram::with_opts(HistogramOpts::new(
+                "craftip_auth_duration_seconds",
+                "time it takes a proxy client to authenticate",
+            ))
+            .map_err(distributor_error!("could not create metric"))?,
+            active_tunnels: IntGauge::new("craftip_active_tunnels", "connected proxy clients")
+                .map_err(distributor_error!("could not create metric"))?,
+            players: IntGauge::new("craftip_players_connected", "connected minecraft clients")
+                .map_err(distributor_error!("could not create metric"))?,
+            tunnel_players: Histogram::with_opts(
+                HistogramOpts::new(
+                    "craftip_tunnel_players",
+                    "players on a tunnel when a player joins",
+                )
+                .buckets(vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0]),
+            )
+            .map_err(distributor_error!("could not create metric"))?,
+            forwarded_bytes: IntCounterVec::new(
+                Opts::new(
+                    "craftip_forwarded_bytes_total",
+                    "payload forwarded between players and tunnels",
+                ),
+                &["direction"],
+            )
+            .map_err(distributor_error!("could not create metric"))?,
+            player_session_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "craftip_player_session_duration_seconds",
+                    "time minecraft clients stay connected",
+                )
+                .buckets(vec![
+                    1.0, 10.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0,
+                ]),
+            )
+            .map_err(distributor_error!("could not create metric"))?,
+            registry,
+        };
+        let own: Vec<Box<dyn Collector>> = vec![
+            Box::new(metrics.connections.clone()),
+            Box::new(metrics.handshake_errors.clone()),
+            Box::new(metrics.auth_failures.clone()),
+            Box::new(metrics.auth_duration.clone()),
+            Box::new(metrics.active_tunnels.clone()),
+            Box::new(metrics.players.clone()),
+            Box::new(metrics.tunnel_players.clone()),
+            Box::new(metrics.forwarded_bytes.clone()),
+            Box::new(metrics.player_session_duration.clone()),
+        ];
+        for collector in own.into_iter().chain(collectors) {
+            metrics
+                .registry
+                .register(collector)
+                .map_err(distributor_error!("could not register metric"))?;
+        }
+        Ok(metrics)
+    }
+    pub fn handshake_error(&self, error: &'static str) {
+        self.handshake_errors.with_label_values(&[error]).inc();
+    }
+    pub fn auth_failure(&self, error: &DistributorError) {
+        self.auth_failures
+            .with_label_values(&[error_label(error)])
+            .inc();
+    }
+    /// encodes all metrics in the prometheus text format
+    pub fn render(&self) -> Result<String, DistributorError> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(distributor_error!("could not encode metrics"))?;
+        String::from_utf8(buffer).map_err(distributor_error!("could not encode metrics"))
+    }
+}
+
+/// label of an error, the error messages can contain hostnames
+pub fn error_label(error: &DistributorError) -> &'static str {
+    match error {
+        DistributorError::ClientNotFound => "client_not_found",
+        DistributorError::ServerNotFound(_) => "server_not_found",
+        DistributorError::ServerAlreadyConnected => "server_already_connected",
+        DistributorError::ServerNotConnected(_) => "server_not_connected",
+        DistributorError::AuthError => "auth_error",
+        DistributorError::Timeout => "timeout",
+        DistributorError::WrongPacket => "wrong_packet",
+        DistributorError::TooManyClients => "too_many_clients",
+        DistributorError::DomainNotVerified(_) => "domain_not_verified",
+        DistributorError::KeyRevoked => "key_revoked",
+        DistributorError::InvalidKeyRotation => "invalid_key_rotation",
+        DistributorError::Banned => "banned",
+        DistributorError::NotAllowed => "not_allowed",
+        DistributorError::PlayerRejected => "player_rejected",
+        DistributorError::RateLimited => "rate_limited",
+        DistributorError::QuotaExceeded => "quota_exceeded",
+        DistributorError::InvalidConfig(_) => "invalid_config",
+        DistributorError::Draining => "draining",
+        DistributorError::UnknownError(_) => "unknown",
+        DistributorError::IoError(_) => "io",
+    }
+}
+
+pub fn codec_error_label(error: &PacketCodecError) -> &'static str {
+    match error {
+        PacketCodecError::MaxLineLengthExceeded => "max_length_exceeded",
+        PacketCodecError::PacketCodec(PacketError::TooSmall) => "too_small",
+        PacketCodecError::PacketCodec(PacketError::NotValid) => "not_valid",
+        PacketCodecError::PacketCodec(PacketError::NotValidStringEncoding) => "string_encoding",
+        PacketCodecError::PacketCodec(PacketError::NotValidFirstPacket) => "not_valid_first_packet",
+        PacketCodecError::PacketCodec(PacketError::NotMatching) => "not_matching",
+        PacketCodecError::PacketCodec(PacketError::EncodingError) => "encoding",
+        PacketCodecError::Io(_) => "io",
+    }
+}
+
+/// serves the metrics on `GET /metrics`
+pub async fn serve_metrics(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+) -> Result<(), DistributorError> {
+    serve(listener, move |request| {
+        let metrics = metrics.clone();
+        async move {
+            if request.method() != Method::GET || request.uri().path() != "/metrics" {
+                return response(StatusCode::NOT_FOUND, "text/plain", "not found");
+            }
+            match metrics.render() {
+                Ok(body) => response(StatusCode::OK, TextEncoder::new().format_type(), body),
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    response(StatusCode::INTERNAL_SERVER_ERROR, "text/plain", "error")
+                }
+            }
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let metrics = Arc::new(Metrics::new(Vec::new()).unwrap());
+        metrics.connections.with_label_values(&["proxy"]).inc();
+        metrics.auth_failure(&DistributorError::ServerNotFound("a.craftip.net".into()));
+        metrics.active_tunnels.inc();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: relay\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut body = String::new();
+        stream.read_to_string(&mut body).await.unwrap();
+        assert!(body.starts_with("HTTP/1.1 200 OK"));
+        assert!(body.contains("craftip_connections_total{protocol=\"proxy\"} 1"));
+        assert!(body.contains("craftip_auth_failures_total{reason=\"server_not_found\"} 1"));
+        assert!(body.contains("craftip_active_tunnels 1"));
+        // hostnames never end up in the labels
+        assert!(!body.contains("a.craftip.net"));
+    }
+}
@@ -1,17 +1,27 @@
-use crate::client_handler::MCClient;
+use crate::client_handler::{read_login, MCClient};
+use crate::metrics::codec_error_label;
 use crate::proxy_handler::ProxyClient;
+use crate::state::RelayState;
 use futures::SinkExt;
-use shared::addressing::{DistributorError, Register};
+use quinn::Connection;
+use shared::addressing::DistributorError;
 use shared::distributor_error;
+use shared::minecraft::MinecraftDataPacket;
 use shared::packet_codec::PacketCodec;
+use shared::proxy::ProxyHelloPacket;
 use shared::socket_packet::SocketPacket;
-use std::sync::Arc;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
 use tokio::time::timeout;
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument, Span};
+
+/// shown to players turned away by a tunnel without a message of its own
+const REJECTED_MESSAGE: &str = "You are not allowed to join this server";
 
 /// This function handles the connection to one client
 /// it decides if the client is a minecraft client or a proxy client
@@ -19,68 +29,374 @@ use tokio_util::codec::Framed;
 /// encapsulates/decapsulates the packets
 pub async fn process_socket_connection(
     socket: TcpStream,
-    register: Arc<Mutex<Register>>,
+    state: RelayState,
 ) -> Result<(), DistributorError> {
-    let mut frames = Framed::new(socket, PacketCodec::new(1024 * 8));
-    // In a loop, read data from the socket and write the data back.
-    let packet = frames.next().await.ok_or(DistributorError::UnknownError(
-        "could not read first packet".to_string(),
-    ))?;
-    let packet = packet.map_err(distributor_error!("could not read packet"))?;
+    let peer_addr = socket
+        .peer_addr()
+        .map_err(distributor_error!("could not get peer addr"))?;
+    let codec = PacketCodec::new(1024 * 8).legacy_proxy(state.config().legacy_proxy_clients);
+    let mut frames = Framed::new(socket, codec);
+    let packet = read_first_packet(&mut frames, &state).await?;
+    let metrics = &state.metrics;
 
     match packet {
         SocketPacket::MCHello(packet) => {
-            let proxy_tx = register.lock().await.servers.get(&packet.hostname).cloned();
+            metrics.connections.with_label_values(&["minecraft"]).inc();
+            Span::current()
+                .record("protocol", "minecraft")
+                .record("hostname", packet.hostname.as_str());
+            state
+                .limiter
+                .check_minecraft(peer_addr.ip())
+                .map_err(|_| DistributorError::RateLimited)?;
+            let proxy_tx = state.get_server(&packet.hostname).await;
             let proxy_tx =
                 proxy_tx.ok_or(DistributorError::ServerNotFound(packet.hostname.clone()))?;
 
-            let mut client = MCClient::new(proxy_tx.clone(), frames, packet).await?;
-
-            client.handle().await?;
-        }
-        SocketPacket::ProxyHello(packet) => {
-            tracing::info!(
-                "Proxy client connected for {} from {}",
-                packet.hostname,
-                frames
-                    .get_ref()
-                    .peer_addr()
-                    .map_err(distributor_error!("could not get peer addr"))?
-            );
-            let mut client = ProxyClient::new(register.clone(), &packet.hostname);
-            // authenticate
-            match timeout(
-                Duration::from_secs(10),
-                client.authenticate(&mut frames, &packet),
-            )
-            .await
-            {
-                Ok(Ok(client)) => client,
-                Err(_) => {
-                    frames
-                        .send(SocketPacket::ProxyError("Timeout".into()))
-                        .await?
+            // lasts from the join until the player leaves
+            async {
+                let (login, login_data) = read_login(&mut frames, &packet).await;
+                let username = login.as_ref().map(|login| login.name.as_str());
+                if let Some(username) = username {
+                    Span::current().record("username", username);
                 }
-                Ok(Err(e)) => {
-                    tracing::warn!("could not add proxy client: {}", e);
-                    frames
-                        .send(SocketPacket::ProxyError(format!(
-                            "Error authenticating: {:?}",
-                            e
-                        )))
-                        .await?;
-                    return Err(e);
+                // rejected players never reach the tunnel
+                if let Some(filter) = state.player_filter(&packet.hostname).await {
+                    if !filter.allows(peer_addr.ip(), username) {
+                        tracing::info!("player rejected by the player filter of the tunnel");
+                        if login.is_some() {
+                            let reason = filter.message.as_deref().unwrap_or(REJECTED_MESSAGE);
+                            let disconnect =
+                                MinecraftDataPacket::disconnect(reason, packet.is_legacy());
+                            frames.send(SocketPacket::from(disconnect)).await?;
+                        }
+                        return Err(DistributorError::PlayerRejected);
+                    }
                 }
-            };
-
-            let response = client.handle(&mut frames).await;
-            client.close_connection().await;
-            response?;
+                let mut client =
+                    MCClient::new(proxy_tx.clone(), frames, packet, login, login_data, &state)
+                        .await?;
+                client.handle().await
+            }
+            .instrument(info_span!("player", username = Empty))
+            .await?;
+        }
+        SocketPacket::ProxyHello(packet) => {
+            metrics.connections.with_label_values(&["proxy"]).inc();
+            process_proxy_connection(frames, packet, peer_addr, state, None).await?;
         }
         _ => {
+            metrics.connections.with_label_values(&["unknown"]).inc();
             tracing::error!("Unknown protocol");
         }
     };
 
     Ok(())
 }
+
+/// reads the first packet, which decides what kind of connection it is,
+/// new connections are turned away while the relay is draining
+pub async fn read_first_packet<T>(
+    frames: &mut Framed<T, PacketCodec>,
+    state: &RelayState,
+) -> Result<SocketPacket, DistributorError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let metrics = &state.metrics;
+    let handshake = timeout(Duration::from_secs(10), frames.next());
+    let packet = match handshake.instrument(info_span!("handshake")).await {
+        Ok(Some(Ok(packet))) => packet,
+        Ok(Some(Err(e))) => {
+            metrics.handshake_error(codec_error_label(&e));
+            return Err(distributor_error!("could not read packet")(e));
+        }
+        Ok(None) => {
+            metrics.handshake_error("closed");
+            return Err(DistributorError::UnknownError(
+                "could not read first packet".to_string(),
+            ));
+        }
+        Err(_) => {
+            metrics.handshake_error("timeout");
+            return Err(DistributorError::Timeout);
+        }
+    };
+    // connected tunnels keep working while the relay is draining
+    if state.is_draining() {
+        if let SocketPacket::ProxyHello(_) = &packet {
+            let e = DistributorError::Draining;
+            frames.send(SocketPacket::ProxyError(e.to_string())).await?;
+        }
+        return Err(DistributorError::Draining);
+    }
+    Ok(packet)
+}
+
+/// authenticates the proxy client and forwards the traffic of its tunnel,
+/// the streams of the players are opened on the QUIC connection if there is one
+pub async fn process_proxy_connection<T>(
+    mut frames: Framed<T, PacketCodec>,
+    packet: ProxyHelloPacket,
+    peer_addr: SocketAddr,
+    state: RelayState,
+    quic: Option<Connection>,
+) -> Result<(), DistributorError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let metrics = &state.metrics;
+    Span::current()
+        .record("protocol", "proxy")
+        .record("hostname", packet.hostname.as_str());
+    tracing::info!("proxy client connected");
+    // the extra connections of a running tunnel are only charged if they turn out to be
+    // something else, so a tunnel can open all of its connections at once
+    let hostname = state.keys.lock().await.resolve(&packet.hostname);
+    let charged = state.get_server(&hostname).await.is_none();
+    if charged {
+        check_proxy_rate(&mut frames, &state, peer_addr).await?;
+    }
+    let mut client = ProxyClient::new(state.clone(), &packet.hostname, quic);
+    // authenticate
+    let auth_start = Instant::now();
+    match timeout(
+        Duration::from_secs(10),
+        client
+            .authenticate(&mut frames, &packet)
+            .instrument(info_span!("authenticate")),
+    )
+    .await
+    {
+        Ok(Ok(())) => metrics
+            .auth_duration
+            .observe(auth_start.elapsed().as_secs_f64()),
+        Err(_) => {
+            if !charged {
+                let _ = state.limiter.check_proxy(peer_addr.ip());
+            }
+            metrics.auth_failure(&DistributorError::Timeout);
+            frames
+                .send(SocketPacket::ProxyError("Timeout".into()))
+                .await?;
+            return Err(DistributorError::Timeout);
+        }
+        Ok(Err(e)) => {
+            if !charged {
+                let _ = state.limiter.check_proxy(peer_addr.ip());
+            }
+            tracing::warn!(error = %e, "could not add proxy client");
+            metrics.auth_failure(&e);
+            frames
+                .send(SocketPacket::ProxyError(format!(
+                    "Error authenticating: {}",
+                    e
+                )))
+                .await?;
+            return Err(e);
+        }
+    };
+
+    if client.is_lane() {
+        return client
+            .attach(&mut frames, peer_addr)
+            .instrument(info_span!("lane"))
+            .await;
+    }
+    if !charged {
+        check_proxy_rate(&mut frames, &state, peer_addr).await?;
+    }
+    metrics.active_tunnels.inc();
+    let response = client
+        .handle(&mut frames, peer_addr)
+        .instrument(info_span!("tunnel"))
+        .await;
+    client.close_connection().await;
+    metrics.active_tunnels.dec();
+    response
+}
+
+/// turns the proxy client away if its subnet opened too many connections lately
+async fn check_proxy_rate<T>(
+    frames: &mut Framed<T, PacketCodec>,
+    state: &RelayState,
+    peer_addr: SocketAddr,
+) -> Result<(), DistributorError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    if state.limiter.check_proxy(peer_addr.ip()).is_err() {
+        let e = DistributorError::RateLimited;
+        frames.send(SocketPacket::ProxyError(e.to_string())).await?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RelayConfig, TxtResolverConfig};
+    use crate::limiter::{LimitsConfig, RateConfig};
+    use crate::state::tests::test_state;
+    use crate::storage::tests::temp_file;
+    use bytes::BytesMut;
+    use shared::config::PROTOCOL_VERSION;
+    use shared::crypto::ServerPrivateKey;
+    use shared::datatypes::PacketError;
+    use shared::proxy::ProxyAuthenticator;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unanswered_challenge() {
+        let state = test_state();
+        let (relay, client) = tokio::io::duplex(1024);
+        let frames = Framed::new(relay, PacketCodec::proxy(1024));
+        let mut client = Framed::new(client, PacketCodec::proxy(1024));
+        let public_key = ServerPrivateKey::default().get_public_key();
+        let hostname = public_key.get_hostname();
+        let hello = ProxyHelloPacket {
+            version: PROTOCOL_VERSION,
+            hostname: hostname.clone(),
+            auth: ProxyAuthenticator::PublicKey(public_key),
+        };
+        let addr = "127.0.0.1:1234".parse().unwrap();
+
+        // the client never signs the challenge
+        let result = process_proxy_connection(frames, hello, addr, state.clone(), None).await;
+        assert!(matches!(result, Err(DistributorError::Timeout)));
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(SocketPacket::ProxyAuthRequest(_)))
+        ));
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(SocketPacket::ProxyError(_)))
+        ));
+        assert!(state.get_server(&hostname).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_client() {
+        let state = test_state();
+        let (relay, mut client) = tokio::io::duplex(1024);
+        let key = ServerPrivateKey::default();
+        let hello = SocketPacket::from(ProxyHelloPacket {
+            version: PROTOCOL_VERSION,
+            hostname: key.get_public_key().get_hostname(),
+            auth: ProxyAuthenticator::PublicKey(key.get_public_key()),
+        });
+        // clients of the legacy format send the bincode encoding without preamble
+        client
+            .write_all(&hello.encode_legacy().unwrap())
+            .await
+            .unwrap();
+        let codec = PacketCodec::new(1024 * 8).legacy_proxy(true);
+        let mut frames = Framed::new(relay, codec);
+        let relay_state = state.clone();
+        tokio::spawn(async move {
+            let packet = read_first_packet(&mut frames, &relay_state).await?;
+            let SocketPacket::ProxyHello(hello) = packet else {
+                return Err(DistributorError::WrongPacket);
+            };
+            let addr = "127.0.0.1:1234".parse().unwrap();
+            process_proxy_connection(frames, hello, addr, relay_state, None).await
+        });
+
+        let mut buf = BytesMut::new();
+        let challenge = match read_legacy(&mut client, &mut buf).await {
+            SocketPacket::ProxyAuthRequest(challenge) => challenge,
+            packet => panic!("unexpected packet {:?}", packet),
+        };
+        let response = SocketPacket::ProxyAuthResponse(key.sign(&challenge));
+        client
+            .write_all(&response.encode_legacy().unwrap())
+            .await
+            .unwrap();
+        // nothing the legacy client does not know is sent before the response
+        assert!(matches!(
+            read_legacy(&mut client, &mut buf).await,
+            SocketPacket::ProxyHelloResponse(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rate_of_lanes() {
+        let config = RelayConfig {
+            limits: LimitsConfig {
+                proxy: RateConfig {
+                    per_second: 0.001,
+                    burst: 1.0,
+                },
+                ..Default::default()
+            },
+            txt_resolver: TxtResolverConfig::File {
+                path: temp_file("txt.json"),
+            },
+            ..Default::default()
+        };
+        let state = RelayState::new(config, None).unwrap();
+        let key = ServerPrivateKey::default();
+        let (_tunnel, response) = connect(&state, &key, false).await;
+        assert!(matches!(response, SocketPacket::ProxyHelloResponse(_)));
+        // the extra connections of the running tunnel are not charged
+        for _ in 0..3 {
+            let (_lane, response) = connect(&state, &key, true).await;
+            assert!(matches!(response, SocketPacket::ProxyHelloResponse(_)));
+        }
+        // a tunnel replacing the running one is
+        let (_tunnel, response) = connect(&state, &key, false).await;
+        assert_eq!(
+            response,
+            SocketPacket::ProxyError(DistributorError::RateLimited.to_string())
+        );
+    }
+
+    /// authenticates a proxy connection, returns the packet the relay answers with
+    async fn connect(
+        state: &RelayState,
+        key: &ServerPrivateKey,
+        attach: bool,
+    ) -> (Framed<DuplexStream, PacketCodec>, SocketPacket) {
+        let (relay, client) = tokio::io::duplex(1024 * 8);
+        let frames = Framed::new(relay, PacketCodec::proxy(1024 * 8));
+        let mut client = Framed::new(client, PacketCodec::proxy(1024 * 8));
+        let hello = ProxyHelloPacket {
+            version: PROTOCOL_VERSION,
+            hostname: key.get_public_key().get_hostname(),
+            auth: ProxyAuthenticator::PublicKey(key.get_public_key()),
+        };
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        tokio::spawn(process_proxy_connection(
+            frames,
+            hello,
+            addr,
+            state.clone(),
+            None,
+        ));
+        if attach {
+            client.send(SocketPacket::ProxyAttach).await.unwrap();
+        }
+        let response = match client.next().await.unwrap().unwrap() {
+            SocketPacket::ProxyAuthRequest(challenge) => {
+                let signature = key.sign(&challenge);
+                client
+                    .send(SocketPacket::ProxyAuthResponse(signature))
+                    .await
+                    .unwrap();
+                client.next().await.unwrap().unwrap()
+            }
+            packet => packet,
+        };
+        (client, response)
+    }
+
+    async fn read_legacy(client: &mut DuplexStream, buf: &mut BytesMut) -> SocketPacket {
+        loop {
+            match SocketPacket::decode_legacy(buf) {
+                Err(PacketError::TooSmall) => client.read_buf(buf).await.unwrap(),
+                result => return result.unwrap(),
+            };
+        }
+    }
+}
@@ -1,69 +1,161 @@
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use futures::{SinkExt, StreamExt};
-use tokio::net::TcpStream;
+use quinn::Connection;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::{mpsc, Mutex};
 use tokio::time::timeout;
 use tokio_util::codec::Framed;
+use tracing::{Instrument, Span};
 
-use shared::addressing::{DistributorError, Register};
+use shared::addressing::{DistributorError, Tx};
+use shared::admin::{unix_timestamp, PlayerInfo, TunnelInfo};
+use shared::batching::WriteBatcher;
+use shared::compression::CompressionAlgorithm;
 use shared::config;
 use shared::config::PROTOCOL_VERSION;
+use shared::crypto::{KeyRotation, ServerPublicKey};
+use shared::distributor_error;
+use shared::lane::run_lane;
 use shared::minecraft::MinecraftDataPacket;
 use shared::packet_codec::PacketCodec;
 use shared::proxy::{
-    ProxyAuthenticator, ProxyConnectedResponse, ProxyDataPacket, ProxyHelloPacket,
+    ProxyAuthenticator, ProxyClientJoinPacket, ProxyConnectedResponse, ProxyDataPacket,
+    ProxyHelloPacket,
 };
+use shared::quic::QuicStream;
+use shared::scheduler::FairQueue;
 use shared::socket_packet::{ClientToProxy, SocketPacket};
 
+use crate::audit::AuditEvent;
+use crate::bandwidth::Throttle;
+use crate::domains::verify_ownership;
+use crate::state::RelayState;
+
+/// traffic is added to the quota in batches of this size
+const QUOTA_BATCH_SIZE: u64 = 1024 * 1024;
+/// shown to players that join a tunnel which has no room left
+const SERVER_FULL_MESSAGE: &str = "The server is full, try again later";
+/// bits of a client id that select the slot of the player, the others are its generation
+const SLOT_BITS: u32 = 10;
+const SLOT_MASK: u16 = (1 << SLOT_BITS) - 1;
+const GENERATIONS: u16 = 1 << (16 - SLOT_BITS);
+
+/// Hands out the ids of the players of a tunnel in constant time.
+///
+/// New slots are used until the limit is reached, then the slots of the players that left,
+/// the oldest first. Every reuse of a slot bumps its generation, which is part of the id, so
+/// data still in flight for a player that left never reaches the next player of the slot.
+#[derive(Debug)]
+struct ClientIds {
+    /// current generation of the slots that have been used
+    generations: Vec<u16>,
+    /// slots of the players that left, the oldest first
+    free: VecDeque<u16>,
+    limit: u16,
+}
+
+impl ClientIds {
+    fn new(limit: u16) -> Self {
+        ClientIds {
+            generations: Vec::new(),
+            free: VecDeque::new(),
+            limit: limit.min(config::MAXIMUM_CLIENTS),
+        }
+    }
+    fn allocate(&mut self) -> Option<u16> {
+        let slot = if self.generations.len() < self.limit as usize {
+            self.generations.push(0);
+            self.generations.len() as u16 - 1
+        } else {
+            self.free.pop_front()?
+        };
+        Some(self.generations[slot as usize] << SLOT_BITS | slot)
+    }
+    fn release(&mut self, id: u16) {
+        let slot = id & SLOT_MASK;
+        match self.generations.get_mut(slot as usize) {
+            Some(generation) if *generation == id >> SLOT_BITS => {
+                *generation = (*generation + 1) % GENERATIONS;
+                self.free.push_back(slot);
+            }
+            _ => tracing::warn!(id, "released an id that is not in use"),
+        }
+    }
+    fn is_exhausted(&self) -> bool {
+        self.generations.len() >= self.limit as usize && self.free.is_empty()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MinecraftClient {
     tx: UnboundedSender<MinecraftDataPacket>,
     id: u16,
+    addr: SocketAddr,
+    connected_at: SystemTime,
+    username: Option<String>,
+    /// extra connection of the tunnel the packets of the player go through,
+    /// the first connection if not set
+    lane: Option<SocketAddr>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Distribiutor {
     clients_addr: HashMap<SocketAddr, MinecraftClient>,
     clients_id: HashMap<u16, SocketAddr>,
+    ids: ClientIds,
 }
 
 impl Distribiutor {
+    /// the tunnel lets `limit` players in at once
+    fn new(limit: u16) -> Self {
+        Distribiutor {
+            clients_addr: HashMap::new(),
+            clients_id: HashMap::new(),
+            ids: ClientIds::new(limit),
+        }
+    }
+    fn is_full(&self) -> bool {
+        self.ids.is_exhausted()
+    }
     fn insert(
         &mut self,
         addr: SocketAddr,
         tx: UnboundedSender<MinecraftDataPacket>,
+        username: Option<String>,
+        lane: Option<SocketAddr>,
     ) -> Result<MinecraftClient, DistributorError> {
-        let mut id = None;
-        let time = std::time::Instant::now();
-        for id_found in 0..=config::MAXIMUM_CLIENTS {
-            if !self.clients_id.contains_key(&id_found) {
-                id = Some(id_found);
-                break;
-            }
-        }
-        tracing::info!("finding id took {:?}", time.elapsed());
-        let id = id.ok_or(DistributorError::TooManyClients)?;
+        let id = self
+            .ids
+            .allocate()
+            .ok_or(DistributorError::TooManyClients)?;
         self.clients_id.insert(id, addr);
-        let client = MinecraftClient { id, tx };
+        let client = MinecraftClient {
+            id,
+            tx,
+            addr,
+            connected_at: SystemTime::now(),
+            username,
+            lane,
+        };
         self.clients_addr.insert(addr, client.clone());
         Ok(client)
     }
-    fn remove_by_addr(&mut self, addr: &SocketAddr) {
-        if let Some(client) = self.clients_addr.get(addr) {
-            self.clients_id.remove(&client.id);
-        }
-        self.clients_addr.remove(addr);
+    fn remove_by_addr(&mut self, addr: &SocketAddr) -> Option<MinecraftClient> {
+        let client = self.clients_addr.remove(addr)?;
+        self.clients_id.remove(&client.id);
+        self.ids.release(client.id);
+        Some(client)
     }
-    fn remove_by_id(&mut self, id: u16) {
-        if let Some(addr) = self.clients_id.get(&id) {
-            self.clients_addr.remove(addr);
-        }
-        self.clients_id.remove(&id);
+    fn remove_by_id(&mut self, id: u16) -> Option<MinecraftClient> {
+        let addr = self.clients_id.remove(&id)?;
+        self.ids.release(id);
+        self.clients_addr.remove(&addr)
     }
     fn get_by_addr(&self, addr: &SocketAddr) -> Option<&MinecraftClient> {
         return self.clients_addr.get(addr);
@@ -74,100 +166,433 @@ impl Distribiutor {
             .get(&id)
             .and_then(|addr| self.clients_addr.get(addr));
     }
+    fn players(&self) -> Vec<PlayerInfo> {
+        let mut players: Vec<_> = self
+            .clients_addr
+            .values()
+            .map(|client| PlayerInfo {
+                id: client.id,
+                addr: client.addr,
+                connected_since: unix_timestamp(client.connected_at),
+                username: client.username.clone(),
+            })
+            .collect();
+        players.sort_by_key(|player| player.id);
+        players
+    }
+    /// moves the packets of the player to an other connection of the tunnel
+    fn set_lane(&mut self, id: u16, lane: Option<SocketAddr>) {
+        if let Some(client) = self
+            .clients_id
+            .get(&id)
+            .and_then(|addr| self.clients_addr.get_mut(addr))
+        {
+            client.lane = lane;
+        }
+    }
+    /// ids of the players whose packets go through the lane
+    fn players_of_lane(&self, lane: &SocketAddr) -> Vec<u16> {
+        self.clients_addr
+            .values()
+            .filter(|client| client.lane.as_ref() == Some(lane))
+            .map(|client| client.id)
+            .collect()
+    }
+}
+
+/// returns the held message before the next one of the channel
+async fn next_message(
+    rx: &mut mpsc::UnboundedReceiver<ClientToProxy>,
+    held: &mut Option<ClientToProxy>,
+) -> Option<ClientToProxy> {
+    match held.take() {
+        Some(message) => Some(message),
+        None => rx.recv().await,
+    }
+}
+
+/// queues the data of a player for the tunnel
+fn queue_packet(
+    distributor: &Distribiutor,
+    queue: &mut FairQueue<u16, Queued>,
+    addr: SocketAddr,
+    pkg: MinecraftDataPacket,
+) {
+    // players turned away still send the data they have read
+    if let Some(client) = distributor.get_by_addr(&addr) {
+        let size = pkg.data.len();
+        let pkg = SocketPacket::from(ProxyDataPacket::new(pkg, client.id));
+        queue.push(client.id, size, Queued::new(client.lane, pkg));
+    }
+}
+
+/// packet for a player, waiting for its turn to be written to the tunnel
+struct Queued {
+    lane: Option<SocketAddr>,
+    packet: SocketPacket,
+    /// player that left, dropping it closes its connection and its stream once the packet is sent
+    left: Option<MinecraftClient>,
+}
+
+impl Queued {
+    fn new(lane: Option<SocketAddr>, packet: SocketPacket) -> Self {
+        Self {
+            lane,
+            packet,
+            left: None,
+        }
+    }
+    fn disconnect(client: MinecraftClient) -> Self {
+        Self {
+            lane: client.lane,
+            packet: SocketPacket::ProxyDisconnect(client.id),
+            left: Some(client),
+        }
+    }
+}
+
+/// extra connections of a tunnel, see [`run_lane`]
+#[derive(Debug, Default)]
+struct Lanes {
+    lanes: Vec<(SocketAddr, UnboundedSender<SocketPacket>)>,
+}
+
+impl Lanes {
+    fn add(&mut self, addr: SocketAddr, tx: UnboundedSender<SocketPacket>) {
+        self.lanes.push((addr, tx));
+    }
+    fn remove(&mut self, addr: &SocketAddr) -> bool {
+        let len = self.lanes.len();
+        self.lanes.retain(|(lane, _)| lane != addr);
+        self.lanes.len() != len
+    }
+    fn len(&self) -> usize {
+        self.lanes.len()
+    }
+    /// picks the connection of a new player by the hash of its address,
+    /// `None` is the first connection of the tunnel
+    fn assign(&self, player: &SocketAddr) -> Option<SocketAddr> {
+        let mut hasher = DefaultHasher::new();
+        player.hash(&mut hasher);
+        let index = hasher.finish() % (self.lanes.len() as u64 + 1);
+        index
+            .checked_sub(1)
+            .map(|index| self.lanes[index as usize].0)
+    }
+    fn get(&self, addr: &SocketAddr) -> Option<&UnboundedSender<SocketPacket>> {
+        self.lanes
+            .iter()
+            .find(|(lane, _)| lane == addr)
+            .map(|(_, tx)| tx)
+    }
+    /// closes the stream of a player that left, if the player had a stream of its own
+    fn release(&mut self, client: Option<MinecraftClient>) {
+        if let Some(client) = client {
+            if client.lane == Some(client.addr) {
+                self.remove(&client.addr);
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
 pub struct ProxyClient {
-    register: Arc<Mutex<Register>>,
+    state: RelayState,
     hostname: String,
+    public_key: Option<ServerPublicKey>,
+    /// algorithms the client offered during the authentication
+    compression_offer: Vec<CompressionAlgorithm>,
+    /// connections the client wants to open for the tunnel
+    connections_requested: u8,
+    /// players the client wants to let in at once, the limit of the relay if not set
+    player_limit_requested: Option<u16>,
+    /// the connection joins the tunnel of the hostname instead of replacing it
+    attach: bool,
+    /// compression of the data packets, selected for the connection
+    compression: Option<CompressionAlgorithm>,
+    /// QUIC connection the streams of the players are opened on, the players share the
+    /// connection of the tunnel if not set
+    quic: Option<Connection>,
 }
 
 impl ProxyClient {
-    pub fn new(register: Arc<Mutex<Register>>, hostname: &str) -> Self {
+    pub fn new(state: RelayState, hostname: &str, quic: Option<Connection>) -> Self {
         ProxyClient {
-            register,
+            state,
             hostname: hostname.to_string(),
+            public_key: None,
+            compression_offer: Vec::new(),
+            connections_requested: 1,
+            player_limit_requested: None,
+            attach: false,
+            compression: None,
+            quic,
         }
     }
     /// HANDLE PROXY CLIENT
-    pub async fn handle(
+    pub async fn handle<T>(
         &mut self,
-        framed: &mut Framed<TcpStream, PacketCodec>,
-    ) -> Result<(), DistributorError> {
+        framed: &mut Framed<T, PacketCodec>,
+        addr: SocketAddr,
+    ) -> Result<(), DistributorError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut info = TunnelInfo {
+            hostname: self.hostname.clone(),
+            addr,
+            connected_since: unix_timestamp(SystemTime::now()),
+            players: 0,
+            uploaded: 0,
+            downloaded: 0,
+        };
+        self.state.audit.record(AuditEvent::TunnelConnected {
+            hostname: self.hostname.clone(),
+            public_key: self.public_key.as_ref().map(|key| key.to_string()),
+            addr: info.addr,
+        });
+        let result = self.forward(framed, &mut info).await;
+        self.state.audit.record(AuditEvent::TunnelDisconnected {
+            hostname: self.hostname.clone(),
+            addr: info.addr,
+            uploaded: info.uploaded,
+            downloaded: info.downloaded,
+        });
+        result
+    }
+    /// forwards the traffic between the tunnel and its players until one side closes
+    async fn forward<T>(
+        &mut self,
+        framed: &mut Framed<T, PacketCodec>,
+        info: &mut TunnelInfo,
+    ) -> Result<(), DistributorError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
         let (tx, mut rx) = mpsc::unbounded_channel();
-        let mut distributor = Distribiutor::default();
+        let mut lanes = Lanes::default();
+        // packets for the players by their id
+        let mut queue: FairQueue<u16, Queued> = FairQueue::default();
+        // message taken from the channel while queueing the data, handled next
+        let mut held = None;
+        let mut config = self.state.subscribe_config();
+        let bandwidth = config.borrow_and_update().bandwidth.clone();
+        let mut batcher = WriteBatcher::new(config.borrow().flush_delay());
+        let mut upload = Throttle::new(bandwidth.tunnel_upload);
+        let mut download = Throttle::new(bandwidth.tunnel_download);
+        // traffic that has not been added to the quota yet
+        let mut traffic = 0;
+        let metrics = self.state.metrics.clone();
+        let uploaded = metrics.forwarded_bytes.with_label_values(&["upload"]);
+        let downloaded = metrics.forwarded_bytes.with_label_values(&["download"]);
 
-        self.register
-            .lock()
-            .await
-            .servers
-            .insert(self.hostname.clone(), tx);
+        let mut register = self.state.register.lock().await;
+        register.servers.insert(self.hostname.clone(), tx.clone());
+        if let Some(public_key) = &self.public_key {
+            register
+                .keys
+                .insert(self.hostname.clone(), public_key.clone());
+        }
+        drop(register);
 
+        let connections = self
+            .connections_requested
+            .min(config.borrow().max_tunnel_connections());
+        if connections > 1 {
+            framed
+                .send(SocketPacket::ProxyConnectionsGranted(connections))
+                .await?;
+        }
+        let max_players = config.borrow().max_players();
+        let player_limit = match self.player_limit_requested {
+            // clients of the legacy format can not decode the answer
+            Some(limit) => {
+                let limit = limit.clamp(1, max_players);
+                framed
+                    .send(SocketPacket::ProxyPlayerLimitGranted(limit))
+                    .await?;
+                limit
+            }
+            None => max_players,
+        };
+        let mut distributor = Distribiutor::new(player_limit);
+        self.select_compression(framed).await?;
         // send connected
         let resp = SocketPacket::from(ProxyConnectedResponse {
             version: PROTOCOL_VERSION,
         });
         framed.send(resp).await?;
         loop {
-            tokio::select! {
+            // packets of the tunnel, received on the first or on an extra connection
+            let packet = tokio::select! {
                 // forward packets from the minecraft clients
-                result = rx.recv() => {
+                result = next_message(&mut rx, &mut held) => {
                     let result = match result {
                         Some(result) => result,
                         None => {
-                            tracing::info!("client channel closed {}", self.hostname);
+                            tracing::info!("client channel closed");
                             break
                         }
                     };
                     match result {
                         ClientToProxy::Close => {
-                            tracing::info!("closing channel for proxy client {}", self.hostname);
+                            tracing::info!("closing channel for proxy client");
                             break
                         },
-                        ClientToProxy::AddMinecraftClient(addr, tx) => {
-                            let client = distributor.insert(addr, tx)?;
-                            framed.send(SocketPacket::ProxyJoin(client.id)).await?;
+                        ClientToProxy::Draining(deadline) => {
+                            framed.send(SocketPacket::ProxyDraining(deadline)).await?;
+                            None
+                        },
+                        ClientToProxy::AddMinecraftClient(addr, player_tx, login, legacy) if distributor.is_full() => {
+                            tracing::info!(player = %addr, "tunnel is full, player turned away");
+                            // status requests have no login and get no answer
+                            if login.is_some() {
+                                let _ = player_tx.send(MinecraftDataPacket::disconnect(SERVER_FULL_MESSAGE, legacy));
+                            }
+                            // dropping the sender closes the connection of the player
+                            None
+                        }
+                        ClientToProxy::AddMinecraftClient(addr, player_tx, login, _) => {
+                            let username = login.as_ref().map(|login| login.name.clone());
+                            let lane = match &self.quic {
+                                // every player gets a stream of its own, so a lost packet only stalls this player
+                                Some(connection) => {
+                                    let delay = config.borrow().flush_delay();
+                                    let stream = spawn_stream(connection.clone(), self.compression, addr, tx.clone(), delay);
+                                    lanes.add(addr, stream);
+                                    Some(addr)
+                                }
+                                None => lanes.assign(&addr),
+                            };
+                            let client = distributor.insert(addr, player_tx, username.clone(), lane)?;
+                            metrics.tunnel_players.observe(distributor.clients_id.len() as f64);
+                            tracing::info!(client_id = client.id, player = %addr, username = username.as_deref(), "player joined");
+                            self.state.audit.record(AuditEvent::PlayerJoined {
+                                hostname: self.hostname.clone(),
+                                client_id: client.id,
+                                addr,
+                                username,
+                            });
+                            let join = ProxyClientJoinPacket::new(client.id, login);
+                            queue.push(client.id, 0, Queued::new(lane, SocketPacket::ProxyJoin(join)));
+                            None
                         },
                         ClientToProxy::Packet(addr, pkg) => {
-                            // if client not found, close connection
-                            let client = distributor.get_by_addr(&addr).ok_or_else(||DistributorError::WrongPacket)?;
-                            let pkg = SocketPacket::from(ProxyDataPacket::new(pkg, client.id));
-                            framed.send(pkg).await?;
+                            queue_packet(&distributor, &mut queue, addr, pkg);
+                            None
                         },
                         ClientToProxy::RemoveMinecraftClient(addr) => {
-                            if let Some(client) = distributor.get_by_addr(&addr) {
-                                framed.send(SocketPacket::ProxyDisconnect(client.id)).await?;
+                            if let Some(client) = distributor.remove_by_addr(&addr) {
+                                tracing::info!(client_id = client.id, player = %addr, "player left");
+                                queue.push(client.id, 0, Queued::disconnect(client));
+                            }
+                            None
+                        }
+                        ClientToProxy::Info(response) => {
+                            info.hostname = self.hostname.clone();
+                            info.players = distributor.clients_addr.len();
+                            let _ = response.send((info.clone(), distributor.players()));
+                            None
+                        }
+                        ClientToProxy::Kick(id, response) => {
+                            let client = distributor.remove_by_id(id);
+                            let kicked = client.is_some();
+                            if let Some(client) = client {
+                                tracing::info!(client_id = id, "kicking player");
+                                queue.push(id, 0, Queued::disconnect(client));
+                            }
+                            let _ = response.send(kicked);
+                            None
+                        }
+                        ClientToProxy::AddLane(addr, lane) => {
+                            if lanes.len() + 1 < connections as usize {
+                                tracing::info!(lane = %addr, "connection joined the tunnel");
+                                lanes.add(addr, lane);
+                            } else {
+                                // dropping the sender closes the connection
+                                tracing::info!(lane = %addr, "tunnel has opened all connections it may open");
+                            }
+                            None
+                        }
+                        ClientToProxy::LanePacket(_, packet) => Some(packet),
+                        ClientToProxy::DomainVerified(domain, result) => {
+                            let response = match self.add_domain(result).await {
+                                Ok(domain) => SocketPacket::ProxyDomainVerified(domain),
+                                Err(e) => {
+                                    tracing::info!(domain, error = %e, "could not add domain");
+                                    SocketPacket::ProxyError(e.to_string())
+                                }
+                            };
+                            framed.send(response).await?;
+                            None
+                        }
+                        ClientToProxy::RemoveLane(addr) => {
+                            if lanes.remove(&addr) {
+                                tracing::info!(lane = %addr, "connection of the tunnel closed");
+                                // the players continue on the remaining connections, a QUIC stream
+                                // only carries its own player, who continues on the first connection
+                                for id in distributor.players_of_lane(&addr) {
+                                    let lane = match &self.quic {
+                                        Some(_) => None,
+                                        None => distributor.get_by_id(id).and_then(|client| lanes.assign(&client.addr)),
+                                    };
+                                    distributor.set_lane(id, lane);
+                                    // the waiting packets keep their order on the new connection
+                                    for queued in queue.remove(&id) {
+                                        let size = match &queued.packet {
+                                            SocketPacket::ProxyData(data) => data.packet.data.len(),
+                                            _ => 0,
+                                        };
+                                        queue.push(id, size, Queued { lane, ..queued });
+                                    }
+                                }
+                            }
+                            None
+                        }
+                    }
+                }
+                // write the packets of the players, taking turns between them
+                _ = std::future::ready(()), if !queue.is_empty() => {
+                    // the data waiting in the channel is queued first, so a player with a lot
+                    // of data can not hold back the others, the other messages are kept in order
+                    while held.is_none() {
+                        match rx.try_recv() {
+                            Ok(ClientToProxy::Packet(addr, pkg)) => {
+                                queue_packet(&distributor, &mut queue, addr, pkg)
                             }
-                            distributor.remove_by_addr(&addr);
+                            Ok(message) => held = Some(message),
+                            Err(_) => break,
                         }
                     }
+                    let (_, queued) = queue.pop().expect("queue is not empty");
+                    if let SocketPacket::ProxyData(data) = &queued.packet {
+                        let size = data.packet.data.len();
+                        download.throttle(size).await;
+                        traffic += size as u64;
+                        downloaded.inc_by(size as u64);
+                        info.downloaded += size as u64;
+                    }
+                    send_to_lane(framed, &mut batcher, &lanes, queued.lane, queued.packet).await?;
+                    lanes.release(queued.left);
+                    None
+                }
+                // write the data frames of the players together
+                _ = batcher.expired() => {
+                    batcher.flush(framed).await?;
+                    None
+                }
+                // the config has been reloaded
+                Ok(()) = config.changed() => {
+                    let bandwidth = config.borrow_and_update().bandwidth.clone();
+                    upload.set_config(bandwidth.tunnel_upload);
+                    download.set_config(bandwidth.tunnel_download);
+                    batcher.set_delay(config.borrow().flush_delay());
+                    None
                 }
                 // handle packets from the proxy client
                 result = timeout(Duration::from_secs(60), framed.next()) => {
                     // catching timeout error
                     match result {
-                        Ok(Some(Ok(packet))) => {
-                            match packet {
-                                // if mc server disconnects mc client
-                                SocketPacket::ProxyDisconnect(client_id) => {
-                                    distributor.remove_by_id(client_id);
-                                }
-                                SocketPacket::ProxyData(packet) => {
-                                    if let Some(client) = distributor.get_by_id(packet.client_id) {
-                                        let mc_packet = MinecraftDataPacket::from(packet);
-                                        if let Err(e) = client.tx.send(mc_packet) {
-                                            tracing::error!("could not send to minecraft client: {}", e);
-                                        }
-                                    }
-                                },
-                                SocketPacket::ProxyPing(packet) => {
-                                    framed.send(SocketPacket::ProxyPong(packet)).await?
-                                }
-                                packet => {
-                                    tracing::info!("Received proxy packet: {:?}", packet);
-                                }
-                            }
-                        }
+                        Ok(Some(Ok(packet))) => Some(packet),
                         // either the channel was closed or the other side closed the channel or timeout
                         e => {
                             tracing::info!("Connection will be closed due to {:?}", e);
@@ -175,21 +600,231 @@ impl ProxyClient {
                         }
                     }
                 }
+            };
+            match packet {
+                // if mc server disconnects mc client
+                Some(SocketPacket::ProxyDisconnect(client_id)) => {
+                    tracing::info!(client_id, "player disconnected by the minecraft server");
+                    for queued in queue.remove(&client_id) {
+                        lanes.release(queued.left);
+                    }
+                    lanes.release(distributor.remove_by_id(client_id));
+                }
+                Some(SocketPacket::ProxyData(packet)) => {
+                    upload.throttle(packet.packet.data.len()).await;
+                    traffic += packet.packet.data.len() as u64;
+                    uploaded.inc_by(packet.packet.data.len() as u64);
+                    info.uploaded += packet.packet.data.len() as u64;
+                    if let Some(client) = distributor.get_by_id(packet.client_id) {
+                        let mc_packet = MinecraftDataPacket::from(packet);
+                        if let Err(e) = client.tx.send(mc_packet) {
+                            tracing::error!("could not send to minecraft client: {}", e);
+                        }
+                    }
+                }
+                Some(SocketPacket::ProxyPing(packet)) => {
+                    framed.send(SocketPacket::ProxyPong(packet)).await?
+                }
+                Some(SocketPacket::ProxyDomainRequest(domain)) => self.verify_domain(domain, &tx),
+                Some(SocketPacket::ProxyPlayerFilter(filter)) => {
+                    tracing::info!("player filter updated");
+                    self.state
+                        .player_filters
+                        .lock()
+                        .await
+                        .insert(self.hostname.clone(), filter);
+                }
+                Some(SocketPacket::ProxyKeyRotation(rotation)) => {
+                    let response = match self.rotate_key(&rotation, &tx).await {
+                        Ok(()) => SocketPacket::ProxyKeyRotated(rotation.new_key),
+                        Err(e) => {
+                            tracing::info!(error = %e, "could not rotate key");
+                            SocketPacket::ProxyError(e.to_string())
+                        }
+                    };
+                    framed.send(response).await?
+                }
+                Some(packet) => {
+                    tracing::info!("Received proxy packet: {:?}", packet);
+                }
+                None => {}
             }
+            if traffic >= QUOTA_BATCH_SIZE {
+                let result = self.add_traffic(traffic).await;
+                traffic = 0;
+                if let Err(e) = result {
+                    tracing::info!(error = %e, "closing proxy client");
+                    framed.send(SocketPacket::ProxyError(e.to_string())).await?;
+                    break;
+                }
+            }
+        }
+        if let Some((sent, received)) = framed.codec().compression_stats() {
+            tracing::info!(
+                download_ratio = sent.ratio(),
+                upload_ratio = received.ratio(),
+                "compression of the tunnel"
+            );
         }
+        let _ = self.add_traffic(traffic).await;
+        self.state.quota.lock().await.save()?;
         Ok(())
     }
+    /// selects the compression before the client is told it is connected
+    async fn select_compression<T>(
+        &mut self,
+        framed: &mut Framed<T, PacketCodec>,
+    ) -> Result<(), DistributorError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let compression = self.state.config().compression;
+        if compression
+            && self
+                .compression_offer
+                .contains(&CompressionAlgorithm::Deflate)
+        {
+            let algorithm = CompressionAlgorithm::Deflate;
+            framed
+                .send(SocketPacket::ProxyCompressionSelected(algorithm))
+                .await?;
+            framed.codec_mut().set_compression(algorithm);
+            self.compression = Some(algorithm);
+            tracing::info!("connection is compressed with {:?}", algorithm);
+        }
+        Ok(())
+    }
+    /// returns true if the client opened the connection to join its tunnel
+    pub fn is_lane(&self) -> bool {
+        self.attach
+    }
+    /// hands the packets of an extra connection to the tunnel of the hostname until
+    /// one of them closes
+    pub async fn attach<T>(
+        &mut self,
+        framed: &mut Framed<T, PacketCodec>,
+        addr: SocketAddr,
+    ) -> Result<(), DistributorError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let tunnel = self
+            .state
+            .get_server(&self.hostname)
+            .await
+            .ok_or_else(|| DistributorError::ServerNotFound(self.hostname.clone()))?;
+        self.select_compression(framed).await?;
+        let resp = SocketPacket::from(ProxyConnectedResponse {
+            version: PROTOCOL_VERSION,
+        });
+        framed.send(resp).await?;
+
+        let (lane_tx, mut lane_rx) = mpsc::unbounded_channel();
+        tunnel
+            .send(ClientToProxy::AddLane(addr, lane_tx))
+            .map_err(|_| DistributorError::ServerNotFound(self.hostname.clone()))?;
+        tracing::info!("connection attached to the tunnel");
+        let mut batcher = WriteBatcher::new(self.state.config().flush_delay());
+        let result = run_lane(framed, &mut lane_rx, &mut batcher, |packet| {
+            tunnel.send(ClientToProxy::LanePacket(addr, packet)).is_ok()
+        })
+        .await;
+        let _ = tunnel.send(ClientToProxy::RemoveLane(addr));
+        result.map_err(distributor_error!("connection of the tunnel failed"))
+    }
+    /// counts the traffic of the tunnel towards its monthly quota
+    async fn add_traffic(&self, bytes: u64) -> Result<(), DistributorError> {
+        self.state
+            .quota
+            .lock()
+            .await
+            .add(&self.hostname, bytes, SystemTime::now())
+    }
     pub async fn close_connection(&mut self) {
         tracing::info!("removing proxy client {} from state", self.hostname);
-        self.register.lock().await.servers.remove(&self.hostname);
+        let mut register = self.state.register.lock().await;
+        register.servers.remove(&self.hostname);
+        register.keys.remove(&self.hostname);
+        drop(register);
+        self.state
+            .player_filters
+            .lock()
+            .await
+            .remove(&self.hostname);
     }
-    pub async fn authenticate(
+    /// revokes the key the client authenticated with and moves the session to the new key
+    async fn rotate_key(
         &mut self,
-        frames: &mut Framed<TcpStream, PacketCodec>,
-        packet: &ProxyHelloPacket,
+        rotation: &KeyRotation,
+        tx: &Tx,
     ) -> Result<(), DistributorError> {
+        if self.public_key.as_ref() != Some(&rotation.old_key) {
+            return Err(DistributorError::InvalidKeyRotation);
+        }
+        let hostname = self.state.keys.lock().await.rotate(rotation)?;
+        let mut register = self.state.register.lock().await;
+        register.servers.remove(&self.hostname);
+        register.keys.remove(&self.hostname);
+        register.servers.insert(hostname.clone(), tx.clone());
+        register
+            .keys
+            .insert(hostname.clone(), rotation.new_key.clone());
+        let mut filters = self.state.player_filters.lock().await;
+        if let Some(filter) = filters.remove(&self.hostname) {
+            filters.insert(hostname.clone(), filter);
+        }
+        tracing::info!(new_hostname = hostname, "key has been rotated");
+        Span::current().record("hostname", hostname.as_str());
+        self.hostname = hostname;
+        self.public_key = Some(rotation.new_key.clone());
+        Ok(())
+    }
+    /// looks up the TXT record of a custom domain in the background, the result is sent to
+    /// the tunnel, so a slow DNS server does not hold up the players
+    fn verify_domain(&self, domain: String, tx: &Tx) {
+        let resolver = self.state.resolver.clone();
+        let public_key = self.public_key.clone();
+        let tx = tx.clone();
+        tokio::spawn(
+            async move {
+                let result = match &public_key {
+                    Some(public_key) => {
+                        verify_ownership(resolver.as_ref(), &domain, public_key).await
+                    }
+                    None => Err(DistributorError::AuthError),
+                };
+                let _ = tx.send(ClientToProxy::DomainVerified(domain, result));
+            }
+            .in_current_span(),
+        );
+    }
+    /// routes a custom domain whose ownership has been verified to this proxy client
+    async fn add_domain(
+        &mut self,
+        verified: Result<String, DistributorError>,
+    ) -> Result<String, DistributorError> {
+        let domain = verified?;
+        self.state
+            .domains
+            .lock()
+            .await
+            .insert(domain.clone(), self.hostname.clone())?;
+        tracing::info!("domain {} is now routed to {}", domain, self.hostname);
+        Ok(domain)
+    }
+    pub async fn authenticate<T>(
+        &mut self,
+        frames: &mut Framed<T, PacketCodec>,
+        packet: &ProxyHelloPacket,
+    ) -> Result<(), DistributorError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
         match &packet.auth {
             ProxyAuthenticator::PublicKey(public_key) => {
+                if self.state.keys.lock().await.is_revoked(public_key) {
+                    return Err(DistributorError::KeyRevoked);
+                }
                 let challenge = public_key.create_challange().map_err(|e| {
                     tracing::error!("Could not create auth challenge: {:?}", e);
                     DistributorError::AuthError
@@ -198,19 +833,47 @@ impl ProxyClient {
 
                 frames.send(auth_request).await?;
 
-                let signature = match frames.next().await {
-                    Some(Ok(SocketPacket::ProxyAuthResponse(signature))) => signature,
-                    e => {
-                        tracing::info!("Client did follow the auth procedure {:?}", e);
-                        return Err(DistributorError::WrongPacket);
+                let signature = loop {
+                    match frames.next().await {
+                        Some(Ok(SocketPacket::ProxyAuthResponse(signature))) => break signature,
+                        // the client sends its options right after the hello
+                        Some(Ok(SocketPacket::ProxyCompressionOffer(offer))) => {
+                            self.compression_offer = offer;
+                        }
+                        Some(Ok(SocketPacket::ProxyConnectionsRequest(count))) => {
+                            self.connections_requested = count;
+                        }
+                        Some(Ok(SocketPacket::ProxyPlayerLimitRequest(limit))) => {
+                            self.player_limit_requested = Some(limit);
+                        }
+                        Some(Ok(SocketPacket::ProxyAttach)) => self.attach = true,
+                        e => {
+                            tracing::info!("Client did follow the auth procedure {:?}", e);
+                            return Err(DistributorError::WrongPacket);
+                        }
                     }
                 };
 
+                // the hostname of a rotated key is routed to the key it has been rotated to
+                let hostname = self.state.keys.lock().await.resolve(&packet.hostname);
                 // verify if client posses the private key
                 if public_key.verify(&challenge, &signature)
-                    && public_key.get_hostname() == packet.hostname
+                    && public_key.get_hostname() == hostname
                 {
-                    tracing::info!("Client {} authenticated successfully", packet.hostname);
+                    self.state
+                        .access
+                        .lock()
+                        .await
+                        .check(public_key, &[&packet.hostname, &hostname])?;
+                    self.state
+                        .quota
+                        .lock()
+                        .await
+                        .check(&hostname, SystemTime::now())?;
+                    Span::current().record("hostname", hostname.as_str());
+                    tracing::info!("proxy client authenticated successfully");
+                    self.hostname = hostname;
+                    self.public_key = Some(public_key.clone());
                     return Ok(());
                 }
             }
@@ -218,3 +881,288 @@ impl ProxyClient {
         Err(DistributorError::AuthError)
     }
 }
+
+/// sends a packet of a player through the connection the player is assigned to
+async fn send_to_lane<T>(
+    framed: &mut Framed<T, PacketCodec>,
+    batcher: &mut WriteBatcher,
+    lanes: &Lanes,
+    lane: Option<SocketAddr>,
+    packet: SocketPacket,
+) -> Result<(), DistributorError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    match lane.and_then(|lane| lanes.get(&lane)) {
+        // the players of a closed lane are moved once it is removed
+        Some(lane) => {
+            let _ = lane.send(packet);
+        }
+        None if matches!(packet, SocketPacket::ProxyData(_)) => {
+            batcher.feed(framed, packet).await?
+        }
+        None => framed.send(packet).await?,
+    }
+    Ok(())
+}
+
+/// opens the stream of a player and forwards its packets in the background,
+/// returns the channel for the packets to send on it
+fn spawn_stream(
+    connection: Connection,
+    compression: Option<CompressionAlgorithm>,
+    player: SocketAddr,
+    tunnel: Tx,
+    delay: Duration,
+) -> UnboundedSender<SocketPacket> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(
+        async move {
+            // opening waits until the client allows another stream, the packets for the
+            // player wait in the channel meanwhile
+            let stream = match connection.open_bi().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::info!(error = %e, "could not open the stream of the player");
+                    let _ = tunnel.send(ClientToProxy::RemoveMinecraftClient(player));
+                    return;
+                }
+            };
+            let mut codec = PacketCodec::proxy(1024 * 8);
+            if let Some(algorithm) = compression {
+                codec.set_compression(algorithm);
+            }
+            let mut stream = Framed::new(QuicStream::new(stream), codec);
+            let mut batcher = WriteBatcher::new(delay);
+            let result = run_lane(&mut stream, &mut rx, &mut batcher, |packet| {
+                tunnel
+                    .send(ClientToProxy::LanePacket(player, packet))
+                    .is_ok()
+            })
+            .await;
+            if let Err(e) = result {
+                tracing::info!(error = %e, "stream of the player failed");
+            }
+            let _ = tunnel.send(ClientToProxy::RemoveLane(player));
+        }
+        .in_current_span(),
+    );
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RelayConfig, TxtResolverConfig};
+    use crate::state::tests::test_state;
+    use crate::storage::tests::temp_file;
+    use shared::crypto::ServerPrivateKey;
+    use tokio::io::DuplexStream;
+
+    #[test]
+    fn test_lanes() {
+        let mut lanes = Lanes::default();
+        let players: Vec<SocketAddr> = (1..=64)
+            .map(|port| SocketAddr::from(([10, 0, 0, 1], port)))
+            .collect();
+        // without extra connections every player uses the first one
+        assert!(players.iter().all(|player| lanes.assign(player).is_none()));
+
+        let lane: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        lanes.add(lane, tx);
+        let assigned: Vec<_> = players.iter().map(|player| lanes.assign(player)).collect();
+        assert!(assigned.contains(&None));
+        assert!(assigned.contains(&Some(lane)));
+        // a player always gets the same connection
+        assert_eq!(lanes.assign(&players[0]), assigned[0]);
+        assert!(lanes.get(&lane).is_some());
+
+        assert!(lanes.remove(&lane));
+        assert!(!lanes.remove(&lane));
+        assert!(players.iter().all(|player| lanes.assign(player).is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_fair_tunnel() {
+        let state = test_state();
+        let hostname = "fair.craftip.net";
+        let mut client = spawn_tunnel(ProxyClient::new(state.clone(), hostname, None));
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(SocketPacket::ProxyHelloResponse(_)))
+        ));
+        let tx = state.get_server(hostname).await.unwrap();
+
+        let (a, b): (SocketAddr, SocketAddr) =
+            ("10.0.1.1:1".parse().unwrap(), "10.0.1.2:1".parse().unwrap());
+        let (a_tx, _a_rx) = mpsc::unbounded_channel();
+        let (b_tx, _b_rx) = mpsc::unbounded_channel();
+        tx.send(ClientToProxy::AddMinecraftClient(a, a_tx, None, false))
+            .unwrap();
+        tx.send(ClientToProxy::AddMinecraftClient(b, b_tx, None, false))
+            .unwrap();
+        // player a downloads a lot of chunks, player b sends a keepalive afterwards
+        for _ in 0..100 {
+            let chunk = MinecraftDataPacket {
+                data: vec![0; 1000].into(),
+            };
+            tx.send(ClientToProxy::Packet(a, chunk)).unwrap();
+        }
+        let keepalive = MinecraftDataPacket {
+            data: vec![1; 8].into(),
+        };
+        tx.send(ClientToProxy::Packet(b, keepalive)).unwrap();
+
+        let mut ids = HashMap::new();
+        let mut chunks_before = 0;
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                SocketPacket::ProxyJoin(join) => {
+                    ids.insert(join.client_id, ids.len());
+                }
+                SocketPacket::ProxyData(data) if ids[&data.client_id] == 0 => chunks_before += 1,
+                SocketPacket::ProxyData(_) => break,
+                packet => panic!("unexpected packet {:?}", packet),
+            }
+        }
+        assert!(
+            chunks_before < 10,
+            "{} chunks were sent first",
+            chunks_before
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_lane() {
+        let config = RelayConfig {
+            max_tunnel_connections: Some(2),
+            txt_resolver: TxtResolverConfig::File {
+                path: temp_file("txt.json"),
+            },
+            ..Default::default()
+        };
+        let state = RelayState::new(config, None).unwrap();
+        let hostname = "lanes.craftip.net";
+        let mut proxy_client = ProxyClient::new(state.clone(), hostname, None);
+        proxy_client.connections_requested = 2;
+        let mut client = spawn_tunnel(proxy_client);
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                SocketPacket::ProxyHelloResponse(_) => break,
+                SocketPacket::ProxyConnectionsGranted(2) => {}
+                packet => panic!("unexpected packet {:?}", packet),
+            }
+        }
+        let tx = state.get_server(hostname).await.unwrap();
+        let lane: SocketAddr = "10.0.0.1:1001".parse().unwrap();
+        let (lane_tx, mut lane_rx) = mpsc::unbounded_channel();
+        tx.send(ClientToProxy::AddLane(lane, lane_tx.clone()))
+            .unwrap();
+
+        // a player whose packets go through the extra connection
+        let mut expected = Lanes::default();
+        expected.add(lane, lane_tx);
+        let player = (1..)
+            .map(|port| SocketAddr::from(([10, 0, 1, 1], port)))
+            .find(|player| expected.assign(player) == Some(lane))
+            .unwrap();
+        let (player_tx, mut player_rx) = mpsc::unbounded_channel();
+        tx.send(ClientToProxy::AddMinecraftClient(
+            player, player_tx, None, false,
+        ))
+        .unwrap();
+        let id = match lane_rx.recv().await {
+            Some(SocketPacket::ProxyJoin(join)) => join.client_id,
+            packet => panic!("unexpected packet {:?}", packet),
+        };
+
+        // the player continues on the first connection once the extra one closes
+        tx.send(ClientToProxy::RemoveLane(lane)).unwrap();
+        let data = MinecraftDataPacket {
+            data: vec![1, 2, 3].into(),
+        };
+        tx.send(ClientToProxy::Packet(player, data.clone()))
+            .unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            SocketPacket::from(ProxyDataPacket::new(data.clone(), id))
+        );
+        client
+            .send(SocketPacket::from(ProxyDataPacket::new(data.clone(), id)))
+            .await
+            .unwrap();
+        assert_eq!(player_rx.recv().await, Some(data));
+    }
+
+    #[tokio::test]
+    async fn test_domain_request() {
+        let key = ServerPrivateKey::default().get_public_key();
+        let records = temp_file("txt-domain-request.json");
+        let challenge = key.get_domain_challenge("mc.example.org");
+        let content = serde_json::json!({ "_craftip.mc.example.org": [challenge] });
+        std::fs::write(&records, content.to_string()).unwrap();
+        let config = RelayConfig {
+            txt_resolver: TxtResolverConfig::File { path: records },
+            ..Default::default()
+        };
+        let state = RelayState::new(config, None).unwrap();
+        let hostname = key.get_hostname();
+        let mut proxy_client = ProxyClient::new(state.clone(), &hostname, None);
+        proxy_client.public_key = Some(key);
+        let mut client = spawn_tunnel(proxy_client);
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(SocketPacket::ProxyHelloResponse(_)))
+        ));
+
+        let request = SocketPacket::ProxyDomainRequest("MC.example.org".to_string());
+        client.send(request).await.unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            SocketPacket::ProxyDomainVerified("mc.example.org".to_string())
+        );
+        let domains = state.domains.lock().await;
+        assert_eq!(domains.get("mc.example.org"), Some(&hostname));
+        drop(domains);
+
+        let request = SocketPacket::ProxyDomainRequest("other.example.org".to_string());
+        client.send(request).await.unwrap();
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(SocketPacket::ProxyError(_)))
+        ));
+    }
+
+    /// runs the tunnel of the proxy client, returns the connection of the client
+    fn spawn_tunnel(mut proxy_client: ProxyClient) -> Framed<DuplexStream, PacketCodec> {
+        let (relay, client) = tokio::io::duplex(1024 * 64);
+        let mut relay = Framed::new(relay, PacketCodec::proxy(1024 * 8));
+        let addr = "10.0.0.1:1000".parse().unwrap();
+        tokio::spawn(async move { proxy_client.handle(&mut relay, addr).await });
+        Framed::new(client, PacketCodec::proxy(1024 * 8))
+    }
+
+    #[test]
+    fn test_client_ids() {
+        let mut ids = ClientIds::new(2);
+        assert_eq!(ids.allocate(), Some(0));
+        assert_eq!(ids.allocate(), Some(1));
+        assert!(ids.is_exhausted());
+        assert_eq!(ids.allocate(), None);
+
+        // the slot is reused with the next generation
+        ids.release(1);
+        ids.release(1);
+        ids.release(0);
+        assert_eq!(ids.allocate(), Some(1 << SLOT_BITS | 1));
+        assert_eq!(ids.allocate(), Some(1 << SLOT_BITS));
+        assert_eq!(ids.allocate(), None);
+        // the generation wraps around
+        for _ in 1..GENERATIONS {
+            ids.release(ids.generations[0] << SLOT_BITS);
+            ids.allocate().unwrap();
+        }
+        assert_eq!(ids.generations[0], 0);
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use quinn::{Endpoint, Incoming};
+use tokio_util::codec::Framed;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use shared::addressing::DistributorError;
+use shared::distributor_error;
+use shared::packet_codec::PacketCodec;
+use shared::quic::{QuicStream, RelayCertificate};
+use shared::socket_packet::SocketPacket;
+
+use crate::process_socket::{process_proxy_connection, read_first_packet};
+use crate::state::RelayState;
+use crate::storage::{load_json, save_json};
+
+/// loads the certificate of the relay, a new one is generated and saved if there is none
+pub fn load_certificate(path: Option<&Path>) -> Result<RelayCertificate, DistributorError> {
+    if let Some(certificate) = load_json::<Option<RelayCertificate>>(path)? {
+        return Ok(certificate);
+    }
+    let certificate =
+        RelayCertificate::generate().map_err(distributor_error!("could not create certificate"))?;
+    save_json(path, &certificate)?;
+    Ok(certificate)
+}
+
+/// accepts the QUIC connections of the proxy clients until the endpoint is closed
+pub async fn serve_quic(endpoint: Endpoint, state: RelayState) {
+    while let Some(incoming) = endpoint.accept().await {
+        let addr = incoming.remote_address();
+        let permit = match state.limiter.accept(addr.ip()) {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                tracing::debug!("refusing connection from {}: {:?}", addr, rejection);
+                incoming.refuse();
+                continue;
+            }
+        };
+        let state = state.clone();
+        let span = tracing::info_span!(
+            "connection",
+            peer = %addr,
+            transport = "quic",
+            protocol = Empty,
+            hostname = Empty
+        );
+        tokio::spawn(
+            async move {
+                // the slot is released when the connection is done
+                let _permit = permit;
+                match process_quic_connection(incoming, state).await {
+                    Ok(_) => tracing::info!("client disconnected"),
+                    Err(DistributorError::UnknownError(err)) => {
+                        tracing::error!(error = %err, "client error")
+                    }
+                    Err(e) => {
+                        tracing::info!(error = ?e, "client error");
+                    }
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// the first stream of the connection is used like the TCP connection of a proxy client
+async fn process_quic_connection(
+    incoming: Incoming,
+    state: RelayState,
+) -> Result<(), DistributorError> {
+    let connection = incoming
+        .await
+        .map_err(distributor_error!("quic handshake failed"))?;
+    let stream = connection
+        .accept_bi()
+        .await
+        .map_err(distributor_error!("could not accept stream"))?;
+    let mut frames = Framed::new(QuicStream::new(stream), PacketCodec::new(1024 * 8));
+    let packet = read_first_packet(&mut frames, &state).await?;
+    state.metrics.connections.with_label_values(&["quic"]).inc();
+    match packet {
+        SocketPacket::ProxyHello(packet) => {
+            let addr = connection.remote_address();
+            process_proxy_connection(frames, packet, addr, state, Some(connection)).await
+        }
+        _ => Err(DistributorError::WrongPacket),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_file;
+
+    #[test]
+    fn test_load_certificate() {
+        let path = temp_file("certificate.json");
+        let certificate = load_certificate(Some(&path)).unwrap();
+        // the clients pin the certificate, so it is kept across restarts
+        let loaded = load_certificate(Some(&path)).unwrap();
+        assert_eq!(
+            loaded.fingerprint().unwrap(),
+            certificate.fingerprint().unwrap()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
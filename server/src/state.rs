@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::{oneshot, watch, Mutex};
+use tokio::time::timeout;
+
+use shared::addressing::{DistributorError, Register, Tx};
+use shared::admin::{unix_timestamp, PlayerInfo, RelayStats, TunnelInfo};
+use shared::crypto::ServerPublicKey;
+use shared::player_filter::PlayerFilter;
+use shared::socket_packet::{ClientID, ClientToProxy};
+
+use crate::access::AccessList;
+use crate::audit::AuditLog;
+use crate::bandwidth::QuotaStore;
+use crate::config::RelayConfig;
+use crate::domains::{create_resolver, DomainStore, TxtResolver};
+use crate::keys::KeyStore;
+use crate::limiter::Limiter;
+use crate::metrics::Metrics;
+
+/// time a tunnel has to answer a query of the admin api
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of the relay shared between all connections
+#[derive(Clone)]
+pub struct RelayState {
+    pub register: Arc<Mutex<Register>>,
+    /// players the tunnels let in, by the hostname of the tunnel
+    pub player_filters: Arc<Mutex<HashMap<String, PlayerFilter>>>,
+    /// the current config, replaced when the config file is reloaded
+    config: Arc<watch::Sender<Arc<RelayConfig>>>,
+    config_path: Option<PathBuf>,
+    pub domains: Arc<Mutex<DomainStore>>,
+    pub resolver: Arc<dyn TxtResolver>,
+    pub keys: Arc<Mutex<KeyStore>>,
+    pub access: Arc<Mutex<AccessList>>,
+    pub limiter: Arc<Limiter>,
+    pub quota: Arc<Mutex<QuotaStore>>,
+    pub metrics: Arc<Metrics>,
+    pub audit: Arc<AuditLog>,
+    /// new connections are refused while the relay is draining
+    pub draining: Arc<AtomicBool>,
+    pub started_at: SystemTime,
+}
+
+impl RelayState {
+    pub fn new(
+        config: RelayConfig,
+        config_path: Option<PathBuf>,
+    ) -> Result<Self, DistributorError> {
+        let domains = DomainStore::load(config.domains_file.clone())?;
+        let resolver = create_resolver(&config.txt_resolver)?;
+        let keys = KeyStore::load(config.key_rotations_file.clone())?;
+        let access = AccessList::load(config.access_list_file.clone())?;
+        let bandwidth = &config.bandwidth;
+        let quota = QuotaStore::load(bandwidth.monthly_quota, bandwidth.quota_file.clone())?;
+        let limiter = Limiter::new(config.limits.clone());
+        let metrics = Metrics::new(vec![Box::new(limiter.rejected.counter.clone())])?;
+        Ok(Self {
+            register: Arc::new(Mutex::new(Register::new())),
+            player_filters: Arc::new(Mutex::new(HashMap::new())),
+            domains: Arc::new(Mutex::new(domains)),
+            resolver,
+            keys: Arc::new(Mutex::new(keys)),
+            access: Arc::new(Mutex::new(access)),
+            limiter: Arc::new(limiter),
+            quota: Arc::new(Mutex::new(quota)),
+            metrics: Arc::new(metrics),
+            audit: Arc::new(AuditLog::new(config.audit.clone())),
+            draining: Arc::new(AtomicBool::new(false)),
+            started_at: SystemTime::now(),
+            config: Arc::new(watch::Sender::new(Arc::new(config))),
+            config_path,
+        })
+    }
+    pub fn config(&self) -> Arc<RelayConfig> {
+        self.config.borrow().clone()
+    }
+    /// sessions use this to pick up new limits without reconnecting
+    pub fn subscribe_config(&self) -> watch::Receiver<Arc<RelayConfig>> {
+        self.config.subscribe()
+    }
+    /// reads the config file again if there is one and reloads the access list
+    pub async fn reload(&self) -> Result<(), DistributorError> {
+        match &self.config_path {
+            Some(path) => self.update_config(RelayConfig::load(path)?).await,
+            None => self.reload_access_list().await,
+        }
+    }
+    /// validates the config and swaps it in for new connections and running sessions
+    pub async fn update_config(&self, config: RelayConfig) -> Result<(), DistributorError> {
+        config.validate()?;
+        for field in self.config().restart_required(&config) {
+            tracing::warn!(
+                "{} has changed, the change takes effect after a restart",
+                field
+            );
+        }
+        self.limiter.update(config.limits.clone());
+        let quota = config.bandwidth.monthly_quota;
+        self.quota.lock().await.set_limit(quota);
+        let access_list_file = config.access_list_file.clone();
+        self.access.lock().await.set_path(access_list_file);
+        self.audit.set_config(config.audit.clone());
+        self.config.send_replace(Arc::new(config));
+        self.reload_access_list().await
+    }
+    /// reloads the access list and closes the tunnels that are not allowed anymore
+    pub async fn reload_access_list(&self) -> Result<(), DistributorError> {
+        let mut access = self.access.lock().await;
+        access.reload()?;
+        self.close_denied_tunnels(&access).await;
+        Ok(())
+    }
+    /// bans the key permanently and closes its tunnel, returns the closed hostnames
+    pub async fn ban_key(&self, key: &ServerPublicKey) -> Result<Vec<String>, DistributorError> {
+        let mut access = self.access.lock().await;
+        access.deny_key(key)?;
+        Ok(self.close_denied_tunnels(&access).await)
+    }
+    async fn close_denied_tunnels(&self, access: &AccessList) -> Vec<String> {
+        let mut closed = Vec::new();
+        let register = self.register.lock().await;
+        for (hostname, tx) in register.servers.iter() {
+            // the same rules as for the authentication
+            let denied = match register.keys.get(hostname) {
+                Some(key) => access.check(key, &[hostname]).is_err(),
+                None => access.denies_hostname(hostname),
+            };
+            if denied {
+                tracing::info!("closing denied tunnel {}", hostname);
+                let _ = tx.send(ClientToProxy::Close);
+                closed.push(hostname.clone());
+            }
+        }
+        closed
+    }
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+    /// refuses new connections and tells the tunnels when they are going to be closed
+    pub async fn drain(&self, deadline: Option<Duration>) {
+        tracing::info!("draining relay, new connections are refused");
+        self.draining.store(true, Ordering::Relaxed);
+        let deadline = deadline.map(|deadline| deadline.as_secs());
+        for tx in self.register.lock().await.servers.values() {
+            let _ = tx.send(ClientToProxy::Draining(deadline));
+        }
+    }
+    pub async fn close_all_tunnels(&self) {
+        for tx in self.register.lock().await.servers.values() {
+            let _ = tx.send(ClientToProxy::Close);
+        }
+    }
+    pub fn stats(&self) -> RelayStats {
+        let bytes = &self.metrics.forwarded_bytes;
+        RelayStats {
+            started_at: unix_timestamp(self.started_at),
+            draining: self.is_draining(),
+            tunnels: self.metrics.active_tunnels.get(),
+            players: self.metrics.players.get(),
+            uploaded: bytes.with_label_values(&["upload"]).get(),
+            downloaded: bytes.with_label_values(&["download"]).get(),
+            rejected_connections: self.limiter.rejected.counts(),
+        }
+    }
+    /// returns the hostname of the tunnel a minecraft client connecting to `hostname` is routed to
+    pub async fn resolve(&self, hostname: &str) -> String {
+        let hostname = match self.domains.lock().await.get(hostname) {
+            Some(server) => server.clone(),
+            None => hostname.to_string(),
+        };
+        self.keys.lock().await.resolve(&hostname)
+    }
+    /// returns the proxy client responsible for the hostname a minecraft client connected to
+    pub async fn get_server(&self, hostname: &str) -> Option<Tx> {
+        let hostname = self.resolve(hostname).await;
+        self.register.lock().await.servers.get(&hostname).cloned()
+    }
+    /// returns the players the tunnel a minecraft client connected to lets in
+    pub async fn player_filter(&self, hostname: &str) -> Option<PlayerFilter> {
+        let hostname = self.resolve(hostname).await;
+        self.player_filters.lock().await.get(&hostname).cloned()
+    }
+    /// returns the state of every connected tunnel, sorted by hostname
+    pub async fn tunnels(&self) -> Vec<(TunnelInfo, Vec<PlayerInfo>)> {
+        let servers: Vec<Tx> = self
+            .register
+            .lock()
+            .await
+            .servers
+            .values()
+            .cloned()
+            .collect();
+        let mut tunnels = Vec::new();
+        for tx in servers {
+            if let Some(tunnel) = query_tunnel(&tx).await {
+                tunnels.push(tunnel);
+            }
+        }
+        tunnels.sort_by(|(a, _), (b, _)| a.hostname.cmp(&b.hostname));
+        tunnels
+    }
+    pub async fn tunnel(&self, hostname: &str) -> Option<(TunnelInfo, Vec<PlayerInfo>)> {
+        let tx = self.register.lock().await.servers.get(hostname).cloned()?;
+        query_tunnel(&tx).await
+    }
+    /// closes the connection of a tunnel, returns false if it is not connected
+    pub async fn close_tunnel(&self, hostname: &str) -> bool {
+        match self.register.lock().await.servers.get(hostname) {
+            Some(tx) => tx.send(ClientToProxy::Close).is_ok(),
+            None => false,
+        }
+    }
+    /// disconnects a player of a tunnel, returns false if there is no such player
+    pub async fn kick_player(&self, hostname: &str, id: ClientID) -> bool {
+        let Some(tx) = self.register.lock().await.servers.get(hostname).cloned() else {
+            return false;
+        };
+        let (response, rx) = oneshot::channel();
+        if tx.send(ClientToProxy::Kick(id, response)).is_err() {
+            return false;
+        }
+        matches!(timeout(QUERY_TIMEOUT, rx).await, Ok(Ok(true)))
+    }
+}
+
+async fn query_tunnel(tx: &Tx) -> Option<(TunnelInfo, Vec<PlayerInfo>)> {
+    let (response, rx) = oneshot::channel();
+    tx.send(ClientToProxy::Info(response)).ok()?;
+    timeout(QUERY_TIMEOUT, rx).await.ok()?.ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::config::{RelayConfig, TxtResolverConfig};
+    use crate::storage::tests::temp_file;
+    use shared::crypto::ServerPrivateKey;
+
+    /// relay state that does not persist anything and does not need dns
+    pub fn test_state() -> RelayState {
+        let config = RelayConfig {
+            txt_resolver: TxtResolverConfig::File {
+                path: temp_file("txt.json"),
+            },
+            ..Default::default()
+        };
+        RelayState::new(config, None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let state = test_state();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let hostname = "test.craftip.net".to_string();
+        state.register.lock().await.servers.insert(hostname, tx);
+        state.drain(Some(Duration::from_secs(30))).await;
+        assert!(state.is_draining());
+        assert!(matches!(
+            rx.recv().await,
+            Some(ClientToProxy::Draining(Some(30)))
+        ));
+        state.close_all_tunnels().await;
+        assert!(matches!(rx.recv().await, Some(ClientToProxy::Close)));
+    }
+
+    #[tokio::test]
+    async fn test_close_denied_tunnels() {
+        let state = test_state();
+        let friend = ServerPrivateKey::default().get_public_key();
+        let stranger = ServerPrivateKey::default().get_public_key();
+        let (friend_tx, mut friend_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (stranger_tx, mut stranger_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut register = state.register.lock().await;
+        for (key, tx) in [(&friend, friend_tx), (&stranger, stranger_tx)] {
+            register.servers.insert(key.get_hostname(), tx);
+            register.keys.insert(key.get_hostname(), key.clone());
+        }
+        drop(register);
+
+        // the relay becomes private, only the friend stays connected
+        let path = temp_file("access-close-denied.json");
+        let content = serde_json::json!({
+            "allow": { "keys": [friend.to_string()] },
+        });
+        std::fs::write(&path, content.to_string()).unwrap();
+        state.access.lock().await.set_path(Some(path.clone()));
+        state.reload_access_list().await.unwrap();
+        assert!(matches!(stranger_rx.try_recv(), Ok(ClientToProxy::Close)));
+        assert!(friend_rx.try_recv().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_config() {
+        let state = test_state();
+        let mut config = state.subscribe_config();
+        let mut invalid = (*state.config()).clone();
+        invalid.limits.ipv6_prefix = 129;
+        assert!(state.update_config(invalid).await.is_err());
+        assert!(!config.has_changed().unwrap());
+
+        let mut new = (*state.config()).clone();
+        new.drain_timeout = Some(5);
+        state.update_config(new).await.unwrap();
+        assert!(config.has_changed().unwrap());
+        assert_eq!(config.borrow_and_update().drain_timeout, Some(5));
+    }
+}
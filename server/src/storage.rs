@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use shared::addressing::DistributorError;
+use shared::distributor_error;
+
+/// reads a JSON file, returns the default value if no path is given or the file does not exist
+pub fn load_json<T: DeserializeOwned + Default>(
+    path: Option<&Path>,
+) -> Result<T, DistributorError> {
+    match path {
+        Some(path) if path.exists() => {
+            let content = fs::read_to_string(path)
+                .map_err(distributor_error!("could not read {}", path.display()))?;
+            serde_json::from_str(&content)
+                .map_err(distributor_error!("could not parse {}", path.display()))
+        }
+        _ => Ok(T::default()),
+    }
+}
+
+/// writes a JSON file by replacing it, so a crash never leaves a half written file behind
+pub fn save_json<T: Serialize>(path: Option<&Path>, value: &T) -> Result<(), DistributorError> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let content = serde_json::to_string_pretty(value)
+        .map_err(distributor_error!("could not serialize {}", path.display()))?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, content).map_err(distributor_error!("could not write {}", tmp.display()))?;
+    fs::rename(&tmp, path).map_err(distributor_error!("could not replace {}", path.display()))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+    /// returns a path in the temp directory that does not exist yet, every call gets a path
+    /// of its own, so tests running in parallel do not share files
+    pub fn temp_file(name: &str) -> PathBuf {
+        let id = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("craftip-{}-{}-{}", std::process::id(), id, name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+}
@@ -0,0 +1,120 @@
+//! Throughput of the data path between the relay and the client.
+//!
+//! Compares the tagged wire format, which slices the data out of the received buffer,
+//! with the legacy format, which copies it while serializing the whole enum with bincode.
+//! Before the measurements the allocations of one round trip are printed.
+//!
+//! Run with `cargo bench -p shared`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use shared::minecraft::MinecraftDataPacket;
+use shared::proxy::ProxyDataPacket;
+use shared::socket_packet::SocketPacket;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const SIZES: [usize; 3] = [64, 1024, 8 * 1024];
+
+fn packet(size: usize) -> SocketPacket {
+    SocketPacket::from(ProxyDataPacket::new(
+        MinecraftDataPacket {
+            data: Bytes::from(vec![0x2A; size]),
+        },
+        1,
+    ))
+}
+
+fn tagged(packet: &SocketPacket, buf: &mut BytesMut) -> SocketPacket {
+    packet.encode_into(buf).unwrap();
+    SocketPacket::decode_proxy(buf).unwrap()
+}
+
+fn legacy(packet: &SocketPacket, buf: &mut BytesMut) -> SocketPacket {
+    buf.extend_from_slice(&packet.encode_legacy().unwrap());
+    SocketPacket::decode_legacy(buf).unwrap()
+}
+
+/// prints the allocations and the allocated bytes of one round trip
+fn report_allocations(
+    name: &str,
+    size: usize,
+    round_trip: fn(&SocketPacket, &mut BytesMut) -> SocketPacket,
+) {
+    let packet = packet(size);
+    let mut buf = BytesMut::with_capacity(16 * 1024);
+    // the first round trip may grow the buffer
+    round_trip(&packet, &mut buf);
+    let (allocations, allocated) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED.load(Ordering::Relaxed),
+    );
+    black_box(round_trip(&packet, &mut buf));
+    println!(
+        "{}/{}: {} allocations, {} bytes per round trip",
+        name,
+        size,
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        ALLOCATED.load(Ordering::Relaxed) - allocated,
+    );
+}
+
+fn proxy_data(c: &mut Criterion) {
+    for size in SIZES {
+        report_allocations("tagged", size, tagged);
+        report_allocations("legacy", size, legacy);
+    }
+    let mut group = c.benchmark_group("proxy_data");
+    for size in SIZES {
+        let packet = packet(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("tagged", size), &packet, |b, packet| {
+            let mut buf = BytesMut::with_capacity(16 * 1024);
+            b.iter(|| black_box(tagged(packet, &mut buf)));
+        });
+        group.bench_with_input(BenchmarkId::new("legacy", size), &packet, |b, packet| {
+            let mut buf = BytesMut::with_capacity(16 * 1024);
+            b.iter(|| black_box(legacy(packet, &mut buf)));
+        });
+    }
+    group.finish();
+}
+
+fn minecraft_data(c: &mut Criterion) {
+    let mut group = c.benchmark_group("minecraft_data");
+    for size in SIZES {
+        let data = vec![0x2A; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            let mut buf = BytesMut::with_capacity(16 * 1024);
+            b.iter(|| {
+                buf.extend_from_slice(data);
+                black_box(MinecraftDataPacket::new(&mut buf).unwrap())
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, proxy_data, minecraft_data);
+criterion_main!(benches);
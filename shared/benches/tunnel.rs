@@ -0,0 +1,102 @@
+//! Packets per second through a tunnel with many players sending at the same time.
+//!
+//! Every player sends small data packets to the tunnel, which writes them to a TCP connection
+//! on the loopback interface, like the relay and the client do. Compares writing every frame
+//! at once with coalescing the frames for the default flush delay.
+//!
+//! Run with `cargo bench -p shared --bench tunnel`.
+
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
+use shared::batching::{WriteBatcher, DEFAULT_FLUSH_DELAY};
+use shared::minecraft::MinecraftDataPacket;
+use shared::packet_codec::PacketCodec;
+use shared::proxy::ProxyDataPacket;
+use shared::socket_packet::SocketPacket;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio_util::codec::{Framed, FramedRead};
+
+const PLAYERS: [u16; 3] = [1, 16, 128];
+const PACKETS_PER_PLAYER: usize = 200;
+/// typical size of the movement and chat packets of a player
+const PACKET_SIZE: usize = 64;
+
+/// sends the packets of all players through one tunnel and waits until they arrived
+async fn run(players: u16, delay: Duration) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stream, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    let mut tunnel = Framed::new(stream.unwrap(), PacketCodec::proxy(64 * 1024));
+    let mut received = FramedRead::new(accepted.unwrap().0, PacketCodec::proxy(64 * 1024));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for client_id in 0..players {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let data = Bytes::from(vec![0x2A; PACKET_SIZE]);
+            for _ in 0..PACKETS_PER_PLAYER {
+                let packet = MinecraftDataPacket { data: data.clone() };
+                let packet = SocketPacket::from(ProxyDataPacket::new(packet, client_id));
+                tx.send(packet).unwrap();
+                // the players take turns like their reads on separate sockets do
+                tokio::task::yield_now().await;
+            }
+        });
+    }
+    drop(tx);
+
+    let writer = tokio::spawn(async move {
+        let mut batcher = WriteBatcher::new(delay);
+        loop {
+            tokio::select! {
+                packet = rx.recv() => match packet {
+                    Some(packet) => batcher.feed(&mut tunnel, packet).await.unwrap(),
+                    None => break,
+                },
+                _ = batcher.expired() => batcher.flush(&mut tunnel).await.unwrap(),
+            }
+        }
+        batcher.flush(&mut tunnel).await.unwrap();
+    });
+    let total = players as usize * PACKETS_PER_PLAYER;
+    for _ in 0..total {
+        received.next().await.unwrap().unwrap();
+    }
+    writer.await.unwrap();
+}
+
+fn tunnel(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("tunnel");
+    group.sample_size(10);
+    for players in PLAYERS {
+        group.throughput(Throughput::Elements(
+            players as u64 * PACKETS_PER_PLAYER as u64,
+        ));
+        for (name, delay) in [
+            ("unbatched", Duration::ZERO),
+            ("batched", DEFAULT_FLUSH_DELAY),
+        ] {
+            group.bench_with_input(BenchmarkId::new(name, players), &players, |b, &players| {
+                b.iter_custom(|iters| {
+                    runtime.block_on(async {
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            run(players, delay).await;
+                        }
+                        start.elapsed()
+                    })
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tunnel);
+criterion_main!(benches);
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use thiserror::Error;
 use tokio::sync::mpsc;
 
+use crate::crypto::ServerPublicKey;
 use crate::socket_packet::ClientToProxy;
 
 pub type Tx = mpsc::UnboundedSender<ClientToProxy>;
@@ -36,6 +37,26 @@ pub enum DistributorError {
     WrongPacket,
     #[error("TooManyClients")]
     TooManyClients,
+    #[error("Ownership of domain {0} could not be verified")]
+    DomainNotVerified(String),
+    #[error("The key has been rotated and is not valid anymore")]
+    KeyRevoked,
+    #[error("Invalid key rotation")]
+    InvalidKeyRotation,
+    #[error("This tunnel has been banned from the relay")]
+    Banned,
+    #[error("This relay is private, the tunnel is not on the allow list")]
+    NotAllowed,
+    #[error("The player is not allowed to join this tunnel")]
+    PlayerRejected,
+    #[error("Too many connections, try again later")]
+    RateLimited,
+    #[error("The monthly traffic quota of this tunnel is used up")]
+    QuotaExceeded,
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("The relay is shutting down, try again later")]
+    Draining,
     #[error("UnknownError")]
     UnknownError(String),
     #[error("IO Error")]
@@ -47,12 +68,15 @@ type ServerHostname = String;
 #[derive(Debug)]
 pub struct Register {
     pub servers: HashMap<ServerHostname, Tx>,
+    /// keys the tunnels authenticated with
+    pub keys: HashMap<ServerHostname, ServerPublicKey>,
 }
 
 impl Register {
     pub fn new() -> Self {
         Register {
             servers: HashMap::new(),
+            keys: HashMap::new(),
         }
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::socket_packet::ClientID;
+
+/// path of the admin socket the command line tool connects to by default
+pub const DEFAULT_ADMIN_SOCKET: &str = "/run/craftip/admin.sock";
+
+/// A tunnel connected to the relay as reported by the admin api
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelInfo {
+    pub hostname: String,
+    pub addr: SocketAddr,
+    /// unix timestamp in seconds
+    pub connected_since: u64,
+    pub players: usize,
+    /// bytes sent by the minecraft server to the players
+    pub uploaded: u64,
+    /// bytes sent by the players to the minecraft server
+    pub downloaded: u64,
+}
+
+/// A minecraft client connected through a tunnel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerInfo {
+    pub id: ClientID,
+    pub addr: SocketAddr,
+    /// unix timestamp in seconds
+    pub connected_since: u64,
+    pub username: Option<String>,
+}
+
+pub fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Command sent to the relay over the admin socket, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum AdminRequest {
+    List,
+    /// adds a public key (base36 encoded) to the deny list and closes its tunnel
+    Ban {
+        key: String,
+    },
+    /// reads the config file and the access list again
+    Reload,
+    /// stops accepting new connections, connected tunnels keep working
+    Drain,
+    Stats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", content = "data", rename_all = "lowercase")]
+pub enum AdminResponse {
+    Tunnels(Vec<TunnelInfo>),
+    /// hostnames of the tunnels that have been closed
+    Banned(Vec<String>),
+    Reloaded,
+    Draining,
+    Stats(RelayStats),
+    Error(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RelayStats {
+    /// unix timestamp in seconds
+    pub started_at: u64,
+    pub draining: bool,
+    pub tunnels: i64,
+    pub players: i64,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// refused connections by the limit that was hit
+    pub rejected_connections: BTreeMap<String, u64>,
+}
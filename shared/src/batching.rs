@@ -0,0 +1,110 @@
+use std::future::pending;
+use std::time::Duration;
+
+use futures::{Sink, SinkExt};
+use tokio::time::{sleep_until, Instant};
+
+use crate::socket_packet::SocketPacket;
+
+/// time the frames of the players may wait to be written together if nothing else is configured
+pub const DEFAULT_FLUSH_DELAY: Duration = Duration::from_millis(1);
+
+/// Coalesces the data frames written to a tunnel. Instead of one write per frame, the frames
+/// are queued in the write buffer of the sink and written together once the oldest of them
+/// has waited for the delay, or earlier if the buffer is full. Frames sent with `send` write
+/// the queued frames as well, so the order is kept.
+#[derive(Debug)]
+pub struct WriteBatcher {
+    delay: Duration,
+    /// the queued frames have to be written at this point
+    deadline: Option<Instant>,
+}
+
+impl WriteBatcher {
+    /// every frame is written at once if the delay is zero
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            deadline: None,
+        }
+    }
+    /// applies to the frames queued after the change
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+    /// queues the frame, it is written after the delay at the latest
+    pub async fn feed<S>(&mut self, sink: &mut S, item: SocketPacket) -> Result<(), S::Error>
+    where
+        S: Sink<SocketPacket> + Unpin,
+    {
+        if self.delay.is_zero() {
+            return sink.send(item).await;
+        }
+        sink.feed(item).await?;
+        if self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + self.delay);
+        }
+        Ok(())
+    }
+    /// resolves once the queued frames have to be written, never if nothing is queued
+    pub async fn expired(&self) {
+        match self.deadline {
+            Some(deadline) => sleep_until(deadline).await,
+            None => pending().await,
+        }
+    }
+    /// writes the queued frames
+    pub async fn flush<S>(&mut self, sink: &mut S) -> Result<(), S::Error>
+    where
+        S: Sink<SocketPacket> + Unpin,
+    {
+        self.deadline = None;
+        SinkExt::<SocketPacket>::flush(sink).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_codec::PacketCodec;
+    use tokio::io::AsyncReadExt;
+    use tokio::time::timeout;
+    use tokio_util::codec::FramedWrite;
+
+    #[tokio::test]
+    async fn test_write_batcher() {
+        let (writer, mut reader) = tokio::io::duplex(1024);
+        let mut sink = FramedWrite::new(writer, PacketCodec::proxy(1024));
+        let mut batcher = WriteBatcher::new(Duration::from_millis(20));
+        let mut buf = [0; 64];
+        let start = Instant::now();
+        batcher
+            .feed(&mut sink, SocketPacket::ProxyPing(1))
+            .await
+            .unwrap();
+        batcher
+            .feed(&mut sink, SocketPacket::ProxyPing(2))
+            .await
+            .unwrap();
+        let read = timeout(Duration::from_millis(1), reader.read(&mut buf)).await;
+        assert!(read.is_err(), "written before the delay");
+
+        batcher.expired().await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        batcher.flush(&mut sink).await.unwrap();
+        // both frames in a single write
+        let ping = SocketPacket::ProxyPing(1).encode().unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 2 * ping.len());
+
+        // nothing is queued, so there is nothing to wait for
+        let expired = timeout(Duration::from_millis(50), batcher.expired()).await;
+        assert!(expired.is_err());
+
+        batcher.set_delay(Duration::ZERO);
+        batcher
+            .feed(&mut sink, SocketPacket::ProxyPing(3))
+            .await
+            .unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), ping.len());
+    }
+}
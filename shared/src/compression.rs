@@ -0,0 +1,141 @@
+use bytes::Bytes;
+use flate2::{Compress, Decompress, FlushCompress, FlushDecompress, Status};
+use serde::{Deserialize, Serialize};
+
+use crate::datatypes::PacketError;
+
+/// data packets are never larger than the frame they were sent in
+const MAX_DECOMPRESSED_SIZE: usize = u16::MAX as usize;
+
+/// Compression of the data packets of a tunnel. The client offers the algorithms it supports
+/// right after the `ProxyHello`, the relay answers with the one it picked before the
+/// `ProxyHelloResponse`. From then on both sides send the data packets compressed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CompressionAlgorithm {
+    /// raw deflate, one stream per direction
+    Deflate,
+}
+
+/// size of the data of the players before and after the compression
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CompressionStats {
+    pub raw: u64,
+    pub compressed: u64,
+}
+
+impl CompressionStats {
+    /// compressed size relative to the raw size, below 1 if the compression saves traffic
+    pub fn ratio(&self) -> f64 {
+        if self.raw == 0 {
+            return 1.0;
+        }
+        self.compressed as f64 / self.raw as f64
+    }
+}
+
+/// Compresses the sent and decompresses the received data packets of a tunnel.
+/// Every packet is flushed so it can be decompressed as soon as it arrives, later packets
+/// are still compressed with the history of the earlier ones, which matters for the small
+/// packets of minecraft. Both sides have to process all packets in the order they are sent.
+#[derive(Debug)]
+pub struct Compressor {
+    compress: Compress,
+    decompress: Decompress,
+    pub sent: CompressionStats,
+    pub received: CompressionStats,
+}
+
+impl Compressor {
+    pub fn new(algorithm: CompressionAlgorithm) -> Self {
+        match algorithm {
+            CompressionAlgorithm::Deflate => Self {
+                // the fastest level, the latency of the players matters more than the ratio
+                compress: Compress::new(flate2::Compression::fast(), false),
+                decompress: Decompress::new(false),
+                sent: CompressionStats::default(),
+                received: CompressionStats::default(),
+            },
+        }
+    }
+    pub fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, PacketError> {
+        let mut output = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if output.len() == output.capacity() {
+                output.reserve(data.len() - consumed + 64);
+            }
+            self.compress
+                .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(|_| PacketError::EncodingError)?;
+            // the flush is complete once the output did not fill the buffer
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+        self.sent.raw += data.len() as u64;
+        self.sent.compressed += output.len() as u64;
+        Ok(output)
+    }
+    pub fn decompress(&mut self, data: &[u8]) -> Result<Bytes, PacketError> {
+        let mut output = Vec::with_capacity(data.len() * 4 + 64);
+        let start = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if output.len() == output.capacity() {
+                if output.len() >= MAX_DECOMPRESSED_SIZE {
+                    return Err(PacketError::NotValid);
+                }
+                output.reserve(output.len().max(1024));
+            }
+            let status = self
+                .decompress
+                .decompress_vec(&data[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|_| PacketError::NotValid)?;
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if status == Status::StreamEnd
+                || consumed == data.len() && output.len() < output.capacity()
+            {
+                break;
+            }
+        }
+        if output.len() > MAX_DECOMPRESSED_SIZE {
+            return Err(PacketError::NotValid);
+        }
+        self.received.raw += output.len() as u64;
+        self.received.compressed += data.len() as u64;
+        Ok(output.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let mut client = Compressor::new(CompressionAlgorithm::Deflate);
+        let mut relay = Compressor::new(CompressionAlgorithm::Deflate);
+        let packets: Vec<Vec<u8>> = vec![
+            b"\x0f\x00\xfb\x05\x09localhost\x63\xdd\x02".to_vec(),
+            vec![0; 10_000],
+            (0..=255).collect(),
+            Vec::new(),
+            b"\x0f\x00\xfb\x05\x09localhost\x63\xdd\x02".to_vec(),
+        ];
+        for packet in &packets {
+            let compressed = client.compress(packet).unwrap();
+            assert_eq!(&relay.decompress(&compressed).unwrap()[..], &packet[..]);
+        }
+        assert_eq!(client.sent.raw, relay.received.raw);
+        assert_eq!(client.sent.compressed, relay.received.compressed);
+        assert!(client.sent.ratio() < 0.1);
+
+        // a packet that decompresses to more than a frame can carry is rejected
+        let bomb = client
+            .compress(&vec![0; 2 * MAX_DECOMPRESSED_SIZE])
+            .unwrap();
+        assert_eq!(relay.decompress(&bomb), Err(PacketError::NotValid));
+    }
+}
@@ -1,5 +1,7 @@
 pub const KEY_SERVER_SUFFIX: &str = ".t.craftip.net";
+pub const DOMAIN_CHALLENGE_SUBDOMAIN: &str = "_craftip";
 pub const SERVER_PORT: u16 = 25565;
-pub const MAXIMUM_CLIENTS: u16 = 255;
+/// most players a tunnel can have at once, the client ids have room for this many
+pub const MAXIMUM_CLIENTS: u16 = 1024;
 pub const PROTOCOL_VERSION: u16 = 1;
 pub const UPDATE_URL: &str = "https://www.craftip.net/update/latest.json";//"https://download.craftip.net/update/v1/latest.json";
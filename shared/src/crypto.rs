@@ -10,6 +10,8 @@ use thiserror::Error;
 const BASE36_ENCODER_STRING: &str = "0123456789abcdefghijklmnopqrstuvwxyz";
 const PREFIX: &str = "CraftIPServerHost";
 const HOSTNAME_LENGTH: usize = 20;
+const DOMAIN_CHALLENGE_PREFIX: &str = "craftip-verify=";
+const KEY_ROTATION_PREFIX: &str = "KeyRotation";
 
 pub type ChallengeDataType = [u8; 64];
 pub type SignatureDataType = [u8; 64];
@@ -20,10 +22,19 @@ pub struct ServerPrivateKey {
     key: [u8; 83],
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct ServerPublicKey {
     key: [u8; 32],
 }
+
+/// Statement signed by an old key that hands its hostname over to a new key
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct KeyRotation {
+    pub old_key: ServerPublicKey,
+    pub new_key: ServerPublicKey,
+    #[serde(with = "BigArray")]
+    pub signature: SignatureDataType,
+}
 fn create_challenge(data: &[u8]) -> Vec<u8> {
     [PREFIX.as_bytes(), data].concat()
 }
@@ -68,6 +79,28 @@ impl ServerPrivateKey {
         result.copy_from_slice(key_pair.public_key().as_ref());
         ServerPublicKey { key: result }
     }
+    /// signs a statement that delegates the hostname of this key to `new_key`
+    pub fn rotate_to(&self, new_key: &ServerPublicKey) -> KeyRotation {
+        KeyRotation {
+            old_key: self.get_public_key(),
+            new_key: new_key.clone(),
+            signature: self.sign(&create_rotation_statement(new_key)),
+        }
+    }
+}
+
+/// the statement is shorter than an auth challenge, a signed challenge can never be a rotation
+fn create_rotation_statement(new_key: &ServerPublicKey) -> Vec<u8> {
+    [KEY_ROTATION_PREFIX.as_bytes(), new_key.key.as_ref()].concat()
+}
+
+impl KeyRotation {
+    /// checks that the old key signed the statement
+    pub fn verify(&self) -> bool {
+        let data = create_challenge(&create_rotation_statement(&self.new_key));
+        let key = signature::UnparsedPublicKey::new(&signature::ED25519, self.old_key.key.as_ref());
+        self.old_key != self.new_key && key.verify(data.as_ref(), &self.signature).is_ok()
+    }
 }
 
 impl fmt::Display for ServerPrivateKey {
@@ -105,6 +138,14 @@ impl ServerPublicKey {
     pub fn get_hostname(&self) -> String {
         format!("{}{}", self.get_host(), config::KEY_SERVER_SUFFIX)
     }
+    /// string that has to be published as TXT record on `_craftip.<domain>`
+    /// to prove that the owner of this key controls the domain
+    pub fn get_domain_challenge(&self, domain: &str) -> String {
+        let checksum = &[PREFIX.as_bytes(), self.key.as_ref(), domain.as_bytes()].concat();
+        let checksum = digest::digest(&digest::SHA256, checksum);
+        let checksum = base_x::encode(BASE36_ENCODER_STRING, checksum.as_ref());
+        format!("{}{}", DOMAIN_CHALLENGE_PREFIX, checksum)
+    }
     pub fn create_challange(&self) -> Result<ChallengeDataType, CryptoError> {
         let rng = rand::SystemRandom::new();
         let mut result = [0u8; 64];
@@ -164,4 +205,32 @@ mod tests {
         let signature = other_private.sign(&challenge);
         assert!(!public.verify(&challenge, &signature));
     }
+    #[test]
+    fn test_key_rotation() {
+        let old = ServerPrivateKey::default();
+        let new = ServerPrivateKey::default();
+        let rotation = old.rotate_to(&new.get_public_key());
+        assert!(rotation.verify());
+        assert_eq!(rotation.old_key, old.get_public_key());
+
+        // the new key can not sign for the old one
+        let forged = new.rotate_to(&new.get_public_key());
+        assert!(!forged.verify());
+        let mut forged = rotation.clone();
+        forged.new_key = ServerPrivateKey::default().get_public_key();
+        assert!(!forged.verify());
+        let mut forged = rotation.clone();
+        forged.old_key = new.get_public_key();
+        assert!(!forged.verify());
+    }
+    #[test]
+    fn test_domain_challenge() {
+        let public = ServerPrivateKey::default().get_public_key();
+        let challenge = public.get_domain_challenge("mc.example.org");
+        assert!(challenge.starts_with("craftip-verify="));
+        assert_eq!(challenge, public.get_domain_challenge("mc.example.org"));
+        assert_ne!(challenge, public.get_domain_challenge("play.example.org"));
+        let other = ServerPrivateKey::default().get_public_key();
+        assert_ne!(challenge, other.get_domain_challenge("mc.example.org"));
+    }
 }
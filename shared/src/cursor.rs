@@ -3,10 +3,11 @@ use bytes::Buf;
 use std::io::Cursor;
 use std::mem::size_of;
 
-pub type CustomCursor = Cursor<Vec<u8>>;
+/// reads from the received data without copying it
+pub type CustomCursor<'a> = Cursor<&'a [u8]>;
 
-pub(crate) trait CustomCursorMethods {
-    fn new(buf: Vec<u8>) -> Self;
+pub(crate) trait CustomCursorMethods<'a> {
+    fn new(buf: &'a [u8]) -> Self;
     fn get_varint(&mut self) -> Result<i32, PacketError>;
     fn get_utf8_string(&mut self) -> Result<String, PacketError>;
     fn throw_error_if_smaller(&mut self, size: usize) -> Result<(), PacketError>;
@@ -14,8 +15,8 @@ pub(crate) trait CustomCursorMethods {
     fn match_bytes(&mut self, bytes: &[u8]) -> bool;
 }
 
-impl CustomCursorMethods for CustomCursor {
-    fn new(buf: Vec<u8>) -> Self {
+impl<'a> CustomCursorMethods<'a> for CustomCursor<'a> {
+    fn new(buf: &'a [u8]) -> Self {
         Self::new(buf)
     }
     /// get the varint form buffer and advance cursor
@@ -27,14 +28,18 @@ impl CustomCursorMethods for CustomCursor {
     /// Returns the string and the size of the string (including the size) in bytes
     fn get_utf8_string(&mut self) -> Result<String, PacketError> {
         let start_postion = self.position();
-        let size = self.get_varint()? as usize;
+        let size = self.get_varint()?;
+        if size < 0 {
+            self.set_position(start_postion);
+            return Err(PacketError::NotValid);
+        }
+        let size = size as usize;
         self.throw_error_if_smaller(size)?;
-        let blob =
-            self.get_ref()[self.position() as usize..self.position() as usize + size].to_owned();
-        let result = String::from_utf8(blob);
+        let blob = &self.get_ref()[self.position() as usize..self.position() as usize + size];
+        let result = std::str::from_utf8(blob);
         self.set_position(self.position() + size as u64);
         match result {
-            Ok(s) => Ok(s),
+            Ok(s) => Ok(s.to_string()),
             Err(_) => {
                 self.set_position(start_postion);
                 Err(PacketError::NotValidStringEncoding)
@@ -26,8 +26,9 @@ pub fn get_varint(buf: &[u8], start: usize) -> Result<(i32, usize), PacketError>
         if size >= 5 {
             return Err(PacketError::NotValid);
         }
+        // the rest of the varint has not arrived yet
         if size + start >= buf.len() {
-            return Err(PacketError::NotValid);
+            return Err(PacketError::TooSmall);
         }
         let current_byte = buf[size + start];
 
@@ -41,9 +42,23 @@ pub fn get_varint(buf: &[u8], start: usize) -> Result<(i32, usize), PacketError>
     }
 }
 
+pub fn put_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Protocol {
     Unknown,
     MC(u32),
     Proxy(u32),
+    /// proxy connection of a client that sent no preamble
+    LegacyProxy(u32),
 }
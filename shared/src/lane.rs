@@ -0,0 +1,100 @@
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::codec::Framed;
+
+use crate::batching::WriteBatcher;
+use crate::packet_codec::{PacketCodec, PacketCodecError};
+use crate::socket_packet::SocketPacket;
+
+/// Forwards the packets of an extra connection of a tunnel.
+///
+/// A tunnel may use several connections between the client and the relay, so a lost
+/// packet only stalls the players of one of them. The first connection carries the control
+/// packets, the extra connections, called lanes, only carry the packets of the players the
+/// relay assigned to them. Every packet of a player goes through the same connection, so
+/// the order of its packets is kept.
+///
+/// Sends the packets of the channel and hands the received ones to `received` until the
+/// connection or the channel is closed, or `received` returns false.
+pub async fn run_lane<T, F>(
+    framed: &mut Framed<T, PacketCodec>,
+    rx: &mut UnboundedReceiver<SocketPacket>,
+    batcher: &mut WriteBatcher,
+    mut received: F,
+) -> Result<(), PacketCodecError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut(SocketPacket) -> bool,
+{
+    loop {
+        tokio::select! {
+            packet = rx.recv() => match packet {
+                Some(packet @ SocketPacket::ProxyData(_)) => batcher.feed(framed, packet).await?,
+                Some(packet) => framed.send(packet).await?,
+                None => break,
+            },
+            _ = batcher.expired() => batcher.flush(framed).await?,
+            packet = framed.next() => match packet {
+                Some(Ok(packet)) => {
+                    if !received(packet) {
+                        break;
+                    }
+                }
+                Some(Err(e)) => return Err(e),
+                None => break,
+            },
+        }
+    }
+    batcher.flush(framed).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batching::DEFAULT_FLUSH_DELAY;
+    use crate::minecraft::MinecraftDataPacket;
+    use crate::proxy::ProxyDataPacket;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_run_lane() {
+        let (relay, client) = tokio::io::duplex(1024);
+        let mut relay = Framed::new(relay, PacketCodec::proxy(1024));
+        let mut client = Framed::new(client, PacketCodec::proxy(1024));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let data = SocketPacket::from(ProxyDataPacket::new(
+            MinecraftDataPacket {
+                data: vec![1, 2, 3].into(),
+            },
+            4,
+        ));
+        tx.send(SocketPacket::ProxyDisconnect(3)).unwrap();
+        tx.send(data.clone()).unwrap();
+        client.send(SocketPacket::ProxyDisconnect(5)).await.unwrap();
+        client.send(SocketPacket::ProxyPing(1)).await.unwrap();
+
+        let mut batcher = WriteBatcher::new(DEFAULT_FLUSH_DELAY);
+        let mut packets = Vec::new();
+        run_lane(&mut relay, &mut rx, &mut batcher, |packet| {
+            packets.push(packet);
+            packets.len() < 2
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            packets,
+            [SocketPacket::ProxyDisconnect(5), SocketPacket::ProxyPing(1)]
+        );
+
+        // the lane closes with the channel, after the queued packets are written
+        drop(tx);
+        run_lane(&mut relay, &mut rx, &mut batcher, |_| true)
+            .await
+            .unwrap();
+        let disconnect = client.next().await.unwrap().unwrap();
+        assert_eq!(disconnect, SocketPacket::ProxyDisconnect(3));
+        assert_eq!(client.next().await.unwrap().unwrap(), data);
+    }
+}
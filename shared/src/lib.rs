@@ -1,11 +1,20 @@
 pub mod addressing;
+pub mod admin;
+pub mod batching;
+pub mod compression;
 pub mod config;
 pub mod crypto;
 mod cursor;
-mod datatypes;
+pub mod datatypes;
+pub mod lane;
+pub mod logging;
 pub mod minecraft;
 pub mod packet_codec;
+pub mod player_filter;
 pub mod proxy;
+pub mod quic;
+pub mod scheduler;
 pub mod socket_packet;
 mod test;
 mod util;
+pub mod wire;
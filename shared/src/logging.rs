@@ -0,0 +1,169 @@
+use std::env;
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{config, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// environment variable selecting the log format, `compact` (default) or `json`
+pub const LOG_FORMAT_ENV: &str = "CRAFTIP_LOG_FORMAT";
+/// environment variable with the OTLP/HTTP collector spans are exported to,
+/// e.g. `http://localhost:4318`. Nothing is exported if it is not set.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Exports the remaining spans when it is dropped, keep it alive until the binary exits.
+#[must_use]
+pub struct TelemetryGuard {
+    provider: Option<TracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            for result in provider.force_flush() {
+                if let Err(e) = result {
+                    eprintln!("could not export spans: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber of a binary. The filter is read from `RUST_LOG`
+/// (e.g. `RUST_LOG=info,server=debug`) and falls back to `default_filter`.
+/// JSON logs contain the fields of all spans an event happened in.
+/// If `OTEL_EXPORTER_OTLP_ENDPOINT` is set, the spans are exported as `service_name`.
+/// Has to be called from within a tokio runtime.
+pub fn init_logging(service_name: &str, default_filter: &str) -> anyhow::Result<TelemetryGuard> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    let json = env::var(LOG_FORMAT_ENV)
+        .map(|format| format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    let (provider, otlp) = match env::var(OTLP_ENDPOINT_ENV) {
+        Ok(endpoint) if !endpoint.is_empty() => {
+            let (provider, layer) = otlp_layer(&endpoint, service_name)?;
+            (Some(provider), Some(layer))
+        }
+        _ => (None, None),
+    };
+    let registry = tracing_subscriber::registry().with(filter).with(otlp);
+    if json {
+        let layer = fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_file(true)
+            .with_line_number(true);
+        registry.with(layer).try_init()?;
+    } else {
+        let layer = fmt::layer()
+            .compact()
+            .with_file(true)
+            .with_line_number(true)
+            .with_thread_ids(false)
+            .with_target(false);
+        registry.with(layer).try_init()?;
+    }
+    Ok(TelemetryGuard { provider })
+}
+
+/// layer exporting the spans in batches to an OTLP/HTTP collector at `endpoint`
+pub fn otlp_layer<S>(
+    endpoint: &str,
+    service_name: &str,
+) -> anyhow::Result<(TracerProvider, OpenTelemetryLayer<S, Tracer>)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint)
+        .build_span_exporter()?;
+    let resource = Resource::new(vec![KeyValue::new(
+        "service.name",
+        service_name.to_string(),
+    )]);
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(config().with_resource(resource))
+        .build();
+    let tracer = provider.tracer("craftip");
+    Ok((provider, tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// stand-in for a collector, sends the path and the body of every request
+    async fn collector(listener: TcpListener, requests: mpsc::UnboundedSender<(String, Vec<u8>)>) {
+        while let Ok((stream, _)) = listener.accept().await {
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                loop {
+                    let mut request_line = String::new();
+                    if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let path = request_line.split(' ').nth(1).unwrap_or("").to_string();
+                    let mut length = 0;
+                    loop {
+                        let mut header = String::new();
+                        stream.read_line(&mut header).await.unwrap();
+                        let header = header.trim_end().to_ascii_lowercase();
+                        if header.is_empty() {
+                            break;
+                        }
+                        if let Some(value) = header.strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    stream.read_exact(&mut body).await.unwrap();
+                    let _ = requests.send((path, body));
+                    let response = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_otlp_export() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, mut requests) = mpsc::unbounded_channel();
+        tokio::spawn(collector(listener, tx));
+
+        let (provider, layer) = otlp_layer(&endpoint, "craftip-test").unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _tunnel = tracing::info_span!("tunnel", hostname = "test.craftip.net").entered();
+            tracing::info_span!("authenticate").in_scope(|| tracing::info!("authenticated"));
+        });
+        // the batch exporter blocks until the spans are sent
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let (path, body) = requests.recv().await.unwrap();
+        assert_eq!(path, "/v1/traces");
+        let contains = |text: &str| body.windows(text.len()).any(|w| w == text.as_bytes());
+        assert!(contains("craftip-test"));
+        assert!(contains("tunnel"));
+        assert!(contains("authenticate"));
+        assert!(contains("test.craftip.net"));
+    }
+}
@@ -1,10 +1,10 @@
 use std::mem::size_of;
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 
 use crate::cursor::{CustomCursor, CustomCursorMethods};
-use crate::datatypes::PacketError;
+use crate::datatypes::{get_varint, put_varint, PacketError};
 use crate::proxy::ProxyDataPacket;
 
 const OLD_MINECRAFT_START: [u8; 27] = [
@@ -19,12 +19,23 @@ pub struct MinecraftHelloPacket {
     pub version: i32,
     pub hostname: String,
     pub port: u32,
+    /// only legacy clients send their username in the first packet
+    pub username: Option<String>,
     pub data: Vec<u8>,
 }
 
+/// Login Start packet, the first packet after a handshake with the next state login
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct MinecraftLoginPacket {
+    pub name: String,
+    /// sent by clients since 1.19.1 (protocol 760)
+    pub uuid: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct MinecraftDataPacket {
-    pub data: Vec<u8>,
+    /// shares the memory of the received data, cloning it does not copy the data
+    pub data: Bytes,
 }
 
 impl From<ProxyDataPacket> for MinecraftDataPacket {
@@ -36,40 +47,118 @@ impl From<ProxyDataPacket> for MinecraftDataPacket {
 impl MinecraftDataPacket {
     pub fn new(buf: &mut BytesMut) -> Result<MinecraftDataPacket, PacketError> {
         Ok(MinecraftDataPacket {
-            data: buf.split_to(buf.len()).to_vec(),
+            data: buf.split().freeze(),
         })
     }
+    /// Disconnect packet of the login state, shown to the player instead of the server
+    pub fn disconnect(reason: &str, legacy: bool) -> MinecraftDataPacket {
+        let mut data = Vec::new();
+        if legacy {
+            // kick packet with an utf16 string
+            let reason: Vec<u16> = reason.encode_utf16().collect();
+            data.push(0xFF);
+            data.extend((reason.len() as u16).to_be_bytes());
+            data.extend(reason.iter().flat_map(|c| c.to_be_bytes()));
+            return MinecraftDataPacket { data: data.into() };
+        }
+        let reason = serde_json::json!({ "text": reason }).to_string();
+        let mut packet = vec![0x00];
+        put_varint(&mut packet, reason.len() as i32);
+        packet.extend(reason.as_bytes());
+        put_varint(&mut data, packet.len() as i32);
+        data.extend(packet);
+        MinecraftDataPacket { data: data.into() }
+    }
 }
 
-impl MinecraftHelloPacket {
-    pub fn new(buf: &mut BytesMut) -> Result<MinecraftHelloPacket, PacketError> {
-        match MinecraftHelloPacket::old_ping_pkg(buf) {
-            Ok(pkg) => return Ok(pkg),
-            Err(PacketError::NotMatching) => {}
-            result => {
-                return result;
-            }
+/// Kind of the first packet of a connection, decided by its first bytes
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FirstPacket {
+    /// server list ping of clients before 1.7, starts with `0xFE 0x01`
+    LegacyPing,
+    /// login of clients before 1.7, starts with `0x02`
+    LegacyConnect,
+    /// handshake of clients since 1.7, starts with the length and the id `0x00`
+    Handshake,
+    /// anything else is left to the proxy protocol
+    Proxy,
+}
+
+/// id, version, a hostname of at least one byte, port and next state
+const MIN_HANDSHAKE_LENGTH: u32 = 6;
+/// the hostnames of proxies like BungeeCord contain the forwarded player data
+const MAX_HANDSHAKE_LENGTH: u32 = 8 * 1024;
+
+impl FirstPacket {
+    /// Looks at the bytes received so far and returns `TooSmall` until the kind is certain,
+    /// so a packet split into several segments is never mistaken for another kind.
+    pub fn detect(buf: &[u8]) -> Result<FirstPacket, PacketError> {
+        enum State {
+            Start,
+            /// `0xFE` is the id of a legacy ping or the first byte of a long length
+            LegacyPing,
+            Length {
+                value: u32,
+                shift: u32,
+            },
+            Id,
         }
-        match MinecraftHelloPacket::old_connect_pkg(buf) {
-            Ok(pkg) => return Ok(pkg),
-            Err(PacketError::NotMatching) => {}
-            result => {
-                return result;
+        fn length(value: u32, shift: u32, byte: u8) -> Result<State, FirstPacket> {
+            let value = value | ((byte & 0x7F) as u32) << shift;
+            if byte & 0x80 != 0 {
+                return match shift + 7 {
+                    // longer than any handshake
+                    shift if shift >= 21 => Err(FirstPacket::Proxy),
+                    shift => Ok(State::Length { value, shift }),
+                };
+            }
+            match value {
+                MIN_HANDSHAKE_LENGTH..=MAX_HANDSHAKE_LENGTH => Ok(State::Id),
+                _ => Err(FirstPacket::Proxy),
             }
         }
-        match MinecraftHelloPacket::new_pkg(buf) {
-            Ok(pkg) => return Ok(pkg),
-            Err(PacketError::NotMatching) => {}
-            result => {
-                return result;
+        let mut state = State::Start;
+        for &byte in buf {
+            let next = match state {
+                State::Start => match byte {
+                    0xFE => Ok(State::LegacyPing),
+                    0x02 => Err(FirstPacket::LegacyConnect),
+                    byte => length(0, 0, byte),
+                },
+                State::LegacyPing if byte == 0x01 => Err(FirstPacket::LegacyPing),
+                State::LegacyPing => length(0x7E, 7, byte),
+                State::Length { value, shift } => length(value, shift, byte),
+                State::Id if byte == 0x00 => Err(FirstPacket::Handshake),
+                State::Id => Err(FirstPacket::Proxy),
+            };
+            match next {
+                Ok(next) => state = next,
+                Err(kind) => return Ok(kind),
             }
         }
+        Err(PacketError::TooSmall)
+    }
+}
+
+impl MinecraftHelloPacket {
+    /// Parses the first packet of a minecraft client, returns `NotMatching` if it is not one
+    /// and `TooSmall` as long as the packet is incomplete.
+    pub fn new(buf: &mut BytesMut) -> Result<MinecraftHelloPacket, PacketError> {
+        match FirstPacket::detect(buf)? {
+            FirstPacket::LegacyPing => MinecraftHelloPacket::old_ping_pkg(buf),
+            FirstPacket::LegacyConnect => MinecraftHelloPacket::old_connect_pkg(buf),
+            FirstPacket::Handshake => MinecraftHelloPacket::new_pkg(buf),
+            FirstPacket::Proxy => Err(PacketError::NotMatching),
+        }
+    }
 
-        Err(PacketError::NotMatching)
+    /// legacy packets start with their id instead of the length of the packet
+    pub fn is_legacy(&self) -> bool {
+        matches!(self.data.first(), Some(0xFE) | Some(0x02))
     }
 
     fn old_ping_pkg(buf: &mut BytesMut) -> Result<MinecraftHelloPacket, PacketError> {
-        let mut cursor = CustomCursor::new(buf.to_vec());
+        let mut cursor = CustomCursor::new(&buf[..]);
         if !cursor.match_bytes(&[0xFE, 0x01]) {
             return Err(PacketError::NotMatching);
         }
@@ -91,17 +180,19 @@ impl MinecraftHelloPacket {
         cursor.throw_error_if_smaller(size_of::<u32>())?;
         let port = cursor.get_u32();
 
+        let length = cursor.position() as usize;
         Ok(MinecraftHelloPacket {
-            length: cursor.position() as usize,
+            length,
             id: 0,
             version: version as i32,
             port,
             hostname,
-            data: buf.split_to(cursor.position() as usize).to_vec(),
+            username: None,
+            data: buf.split_to(length).to_vec(),
         })
     }
     fn old_connect_pkg(buf: &mut BytesMut) -> Result<MinecraftHelloPacket, PacketError> {
-        let mut cursor = CustomCursor::new(buf.to_vec());
+        let mut cursor = CustomCursor::new(&buf[..]);
         if !cursor.match_bytes(&[0x02]) {
             return Err(PacketError::NotMatching);
         }
@@ -109,43 +200,132 @@ impl MinecraftHelloPacket {
         cursor.throw_error_if_smaller(size_of::<u8>())?;
         let version = cursor.get_u8();
         // wait for the packet to fully arrive
-        let _username = cursor.get_utf16_string()?;
+        let username = cursor.get_utf16_string()?;
         let hostname = cursor.get_utf16_string()?;
         cursor.throw_error_if_smaller(size_of::<u32>())?;
         let port = cursor.get_u32();
 
+        let length = cursor.position() as usize;
         Ok(MinecraftHelloPacket {
-            length: cursor.position() as usize,
+            length,
             id: 0,
             version: version as i32,
             port,
             hostname,
-            data: buf.split_to(cursor.position() as usize).to_vec(),
+            username: Some(username),
+            data: buf.split_to(length).to_vec(),
         })
     }
 
     fn new_pkg(buf: &mut BytesMut) -> Result<MinecraftHelloPacket, PacketError> {
-        let mut cursor = CustomCursor::new(buf.to_vec());
-        let pkg_length = cursor.get_varint()?;
-        let pkg_id = cursor.get_varint()?;
-        if pkg_id != 0 {
-            return Err(PacketError::NotMatching);
-        }
-        let version = cursor.get_varint()?;
-        let hostname = cursor.get_utf8_string()?;
-        cursor.throw_error_if_smaller(size_of::<u16>())?;
-        let port = cursor.get_u16();
-        if cursor.position() as usize != pkg_length as usize {
+        let (pkg_length, length_size) = get_varint(buf, 0)?;
+        let end = length_size + pkg_length as usize;
+        // the fields can not reach beyond the end of the packet
+        let mut cursor = CustomCursor::new(&buf[..end.min(buf.len())]);
+        cursor.set_position(length_size as u64);
+        let read_fields = |cursor: &mut CustomCursor| {
+            let pkg_id = cursor.get_varint()?;
+            if pkg_id != 0 {
+                return Err(PacketError::NotMatching);
+            }
+            let version = cursor.get_varint()?;
+            let hostname = cursor.get_utf8_string()?;
+            cursor.throw_error_if_smaller(size_of::<u16>())?;
+            let port = cursor.get_u16();
+            Ok((pkg_id, version, hostname, port))
+        };
+        let (pkg_id, version, hostname, port) = match read_fields(&mut cursor) {
+            Err(PacketError::TooSmall) if buf.len() >= end => Err(PacketError::NotValid),
+            result => result,
+        }?;
+        // only the next state is left, it is forwarded with the following data
+        if cursor.position() as usize - length_size + 1 != pkg_length as usize {
             return Err(PacketError::NotValid);
         }
 
+        let length = cursor.position() as usize;
         Ok(MinecraftHelloPacket {
-            length: cursor.position() as usize,
+            length,
             id: pkg_id,
             port: port as u32,
             version,
             hostname,
-            data: buf.split_to(cursor.position() as usize).to_vec(),
+            username: None,
+            data: buf.split_to(length).to_vec(),
         })
     }
 }
+
+impl MinecraftLoginPacket {
+    /// Parses the data a client sends after the handshake packet: the next state of
+    /// the handshake, which is not part of `MinecraftHelloPacket`, followed by the
+    /// Login Start packet. Returns `NotMatching` if the client only asks for the status.
+    pub fn new(buf: &[u8], version: i32) -> Result<MinecraftLoginPacket, PacketError> {
+        let mut cursor = CustomCursor::new(buf);
+        cursor.throw_error_if_smaller(size_of::<u8>())?;
+        if cursor.get_varint()? != 2 {
+            return Err(PacketError::NotMatching);
+        }
+        cursor.throw_error_if_smaller(size_of::<u8>())?;
+        let length = cursor.get_varint()? as usize;
+        cursor.throw_error_if_smaller(length)?;
+        // the rest of the buffer belongs to the following packets
+        let start = cursor.position() as usize;
+        let mut cursor = CustomCursor::new(&buf[start..start + length]);
+        if cursor.get_varint()? != 0 {
+            return Err(PacketError::NotValid);
+        }
+        let name = cursor.get_utf8_string()?;
+        if name.is_empty() || name.len() > 16 {
+            return Err(PacketError::NotValid);
+        }
+        // the uuid is optional, a packet without one is still a valid login
+        let uuid = Self::uuid(&mut cursor, version).ok().flatten();
+        Ok(MinecraftLoginPacket { name, uuid })
+    }
+    fn uuid(cursor: &mut CustomCursor, version: i32) -> Result<Option<String>, PacketError> {
+        let has_uuid = match version {
+            // 1.19.1 and 1.19.2 send the signature data of the chat key first
+            760 => {
+                cursor.throw_error_if_smaller(size_of::<u8>())?;
+                if cursor.get_u8() != 0 {
+                    cursor.throw_error_if_smaller(size_of::<i64>())?;
+                    cursor.advance(size_of::<i64>());
+                    for _ in 0..2 {
+                        let size = cursor.get_varint()? as usize;
+                        cursor.throw_error_if_smaller(size)?;
+                        cursor.advance(size);
+                    }
+                }
+                cursor.throw_error_if_smaller(size_of::<u8>())?;
+                cursor.get_u8() != 0
+            }
+            // 1.19.3 to 1.20.1
+            761..=763 => {
+                cursor.throw_error_if_smaller(size_of::<u8>())?;
+                cursor.get_u8() != 0
+            }
+            // since 1.20.2 the uuid is always sent
+            764.. => true,
+            _ => false,
+        };
+        if !has_uuid {
+            return Ok(None);
+        }
+        cursor.throw_error_if_smaller(size_of::<u128>())?;
+        Ok(Some(format_uuid(cursor.get_u128())))
+    }
+}
+
+/// formats a uuid like minecraft does, e.g. `069a79f4-44e9-4726-a5be-fca90e38aaf5`
+pub fn format_uuid(uuid: u128) -> String {
+    let hex = format!("{:032x}", uuid);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
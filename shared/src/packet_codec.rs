@@ -1,6 +1,10 @@
+use crate::compression::{CompressionAlgorithm, CompressionStats, Compressor};
+use crate::config::PROTOCOL_VERSION;
 use crate::datatypes::PacketError;
 use crate::datatypes::Protocol;
-use crate::socket_packet::SocketPacket;
+use crate::minecraft::MinecraftDataPacket;
+use crate::proxy::ProxyDataPacket;
+use crate::socket_packet::{SocketPacket, PROXY_PREAMBLE};
 use bytes::{BufMut, Bytes, BytesMut};
 use std::io;
 use thiserror::Error;
@@ -21,12 +25,40 @@ pub enum PacketCodecError {
 
 impl PacketCodec {
     /// Returns a `PacketCodec` for splitting up data into packets.
+    /// The first packet decides if the connection is a minecraft or a proxy connection.
     pub fn new(max_length: usize) -> PacketCodec {
         PacketCodec {
             max_length,
             protocol: Protocol::Unknown,
+            preamble: false,
+            legacy_proxy: false,
+            compressor: None,
         }
     }
+    /// Returns a `PacketCodec` for connections that only carry proxy packets, like the
+    /// connection of the client to the relay.
+    pub fn proxy(max_length: usize) -> PacketCodec {
+        PacketCodec {
+            protocol: Protocol::Proxy(PROTOCOL_VERSION as u32),
+            ..PacketCodec::new(max_length)
+        }
+    }
+    /// Accepts proxy connections of older clients that do not send the preamble.
+    /// Everything that is not a minecraft handshake is decoded as proxy packet then.
+    pub fn legacy_proxy(mut self, legacy_proxy: bool) -> PacketCodec {
+        self.legacy_proxy = legacy_proxy;
+        self
+    }
+    /// compresses the data packets sent after this call and decompresses the received ones
+    pub fn set_compression(&mut self, algorithm: CompressionAlgorithm) {
+        self.compressor = Some(Compressor::new(algorithm));
+    }
+    /// returns the sizes of the sent and of the received data if the tunnel is compressed
+    pub fn compression_stats(&self) -> Option<(CompressionStats, CompressionStats)> {
+        self.compressor
+            .as_ref()
+            .map(|compressor| (compressor.sent, compressor.received))
+    }
 }
 
 impl From<io::Error> for PacketCodecError {
@@ -41,10 +73,14 @@ impl From<PacketError> for PacketCodecError {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct PacketCodec {
     max_length: usize,
     protocol: Protocol,
+    /// the proxy preamble has been read, the next packet is the `ProxyHello`
+    preamble: bool,
+    legacy_proxy: bool,
+    compressor: Option<Compressor>,
 }
 
 impl Decoder for PacketCodec {
@@ -62,8 +98,27 @@ impl Decoder for PacketCodec {
         let result = match self.protocol {
             // first packet
             Protocol::Unknown => {
-                let result = SocketPacket::parse_first_package(buf);
+                if !self.preamble {
+                    match SocketPacket::strip_preamble(buf) {
+                        Ok(preamble) => self.preamble = preamble,
+                        Err(PacketError::TooSmall) => return Ok(None),
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                let result = if self.preamble {
+                    match SocketPacket::decode_proxy(buf) {
+                        Ok(packet @ SocketPacket::ProxyHello(_)) => Ok(packet),
+                        Ok(_) => Err(PacketError::NotValidFirstPacket),
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    SocketPacket::parse_first_package(buf, self.legacy_proxy)
+                };
                 match result.as_ref() {
+                    Ok(SocketPacket::ProxyHello(pkg)) if !self.preamble => {
+                        tracing::debug!("::::::::::::: Changing connection to legacy proxy protocol version {} ::::::::::::::", pkg.version);
+                        self.protocol = Protocol::LegacyProxy(pkg.version as u32);
+                    }
                     Ok(SocketPacket::ProxyHello(pkg)) => {
                         tracing::debug!("::::::::::::: Changing connection to proxy protocol version {} ::::::::::::::", pkg.version);
                         self.protocol = Protocol::Proxy(pkg.version as u32);
@@ -78,7 +133,16 @@ impl Decoder for PacketCodec {
                 }
                 result
             }
-            _ => SocketPacket::parse_packet(buf, &self.protocol),
+            _ => match SocketPacket::parse_packet(buf, &self.protocol) {
+                Ok(SocketPacket::ProxyCompressedData(packet)) => match &mut self.compressor {
+                    Some(compressor) => compressor.decompress(&packet.packet.data).map(|data| {
+                        let data = MinecraftDataPacket { data };
+                        SocketPacket::from(ProxyDataPacket::new(data, packet.client_id))
+                    }),
+                    None => Err(PacketError::NotValid),
+                },
+                result => result,
+            },
         };
         match result {
             Ok(packet) => Ok(packet).map(Some),
@@ -113,19 +177,38 @@ impl Encoder<SocketPacket> for PacketCodec {
     type Error = io::Error;
 
     fn encode(&mut self, pkg: SocketPacket, buf: &mut BytesMut) -> Result<(), io::Error> {
-        let data = match pkg {
-            SocketPacket::MCHello(packet) => packet.data,
-            SocketPacket::MCData(packet) => packet.data,
+        let encoding_error = |e| io::Error::new(io::ErrorKind::Other, e);
+        match pkg {
+            SocketPacket::MCHello(packet) => buf.extend_from_slice(&packet.data),
+            SocketPacket::MCData(packet) => buf.extend_from_slice(&packet.data),
             SocketPacket::Unknown => {
                 tracing::error!("UnknownPacket: {:?}", pkg);
-                "UnknownPacket".to_string().into_bytes()
+                buf.extend_from_slice(b"UnknownPacket");
             }
-            packet => packet
-                .encode()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
-        };
-        buf.reserve(data.len());
-        buf.put(&data[..]);
+            // optional packets are dropped, legacy clients can not skip them
+            packet if matches!(self.protocol, Protocol::LegacyProxy(_)) => match packet.is_legacy()
+            {
+                true => buf.extend_from_slice(&packet.encode_legacy().map_err(encoding_error)?),
+                false => tracing::debug!("not sending {:?} to a legacy client", packet),
+            },
+            SocketPacket::ProxyData(packet) if self.compressor.is_some() => {
+                let compressor = self.compressor.as_mut().expect("checked by the guard");
+                let data = compressor
+                    .compress(&packet.packet.data)
+                    .map_err(encoding_error)?;
+                let data = MinecraftDataPacket { data: data.into() };
+                let packet = ProxyDataPacket::new(data, packet.client_id);
+                SocketPacket::ProxyCompressedData(packet)
+                    .encode_into(buf)
+                    .map_err(encoding_error)?
+            }
+            // the relay recognizes proxy connections by the preamble
+            packet @ SocketPacket::ProxyHello(_) => {
+                buf.extend_from_slice(PROXY_PREAMBLE);
+                packet.encode_into(buf).map_err(encoding_error)?
+            }
+            packet => packet.encode_into(buf).map_err(encoding_error)?,
+        }
         Ok(())
     }
 }
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Players a tunnel lets in. The client sends it to the relay after the authentication,
+/// so the relay turns the other players away before they reach the minecraft server.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct PlayerFilter {
+    /// if set, only the players on this list are let in
+    pub whitelist: Option<PlayerList>,
+    pub blacklist: PlayerList,
+    /// shown to the players that are turned away
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct PlayerList {
+    /// compared without regard to case
+    pub usernames: Vec<String>,
+    pub networks: Vec<IpNetwork>,
+}
+
+impl PlayerList {
+    fn contains(&self, ip: IpAddr, username: Option<&str>) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+            || username.is_some_and(|username| {
+                self.usernames
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(username))
+            })
+    }
+}
+
+impl PlayerFilter {
+    /// Players without a username, e.g. status requests of the server list,
+    /// can only be matched by their address.
+    pub fn allows(&self, ip: IpAddr, username: Option<&str>) -> bool {
+        if self.blacklist.contains(ip, username) {
+            return false;
+        }
+        match &self.whitelist {
+            Some(whitelist) => whitelist.contains(ip, username),
+            None => true,
+        }
+    }
+}
+
+/// IP address range like `192.168.0.0/16`, a single address if the prefix is left out
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // players connecting over IPv6 to a dual stack socket have mapped IPv4 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("{} is not a valid ip address", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("{} is not a valid prefix length", prefix))?,
+            None => max,
+        };
+        Ok(IpNetwork { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<IpNetwork> for String {
+    fn from(network: IpNetwork) -> Self {
+        network.to_string()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_filter() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let filter: PlayerFilter = serde_json::from_str(
+            r#"{
+                "whitelist": { "usernames": ["Notch"], "networks": ["10.0.0.0/8", "fd00::/8"] },
+                "blacklist": { "usernames": ["Griefer"], "networks": ["10.0.0.66"] }
+            }"#,
+        )
+        .unwrap();
+        assert!(filter.allows(ip("1.2.3.4"), Some("notch")));
+        assert!(filter.allows(ip("10.1.2.3"), None));
+        assert!(filter.allows(ip("::ffff:10.1.2.3"), None));
+        assert!(filter.allows(ip("fd12::1"), Some("Steve")));
+        assert!(!filter.allows(ip("1.2.3.4"), Some("Steve")));
+        assert!(!filter.allows(ip("1.2.3.4"), None));
+        assert!(!filter.allows(ip("10.0.0.66"), Some("Notch")));
+        assert!(!filter.allows(ip("10.1.2.3"), Some("griefer")));
+
+        let blacklist = PlayerFilter {
+            blacklist: filter.blacklist.clone(),
+            ..Default::default()
+        };
+        assert!(blacklist.allows(ip("1.2.3.4"), Some("Steve")));
+        assert!(!blacklist.allows(ip("10.0.0.66"), None));
+
+        assert_eq!(
+            "0.0.0.0/0".parse::<IpNetwork>().unwrap().to_string(),
+            "0.0.0.0/0"
+        );
+        assert!("0.0.0.0/0"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains(ip("8.8.8.8")));
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("steve".parse::<IpNetwork>().is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use crate::crypto::{ChallengeDataType, ServerPublicKey, SignatureDataType};
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
 
-use crate::minecraft::{MinecraftDataPacket, MinecraftHelloPacket};
+use crate::minecraft::{MinecraftDataPacket, MinecraftHelloPacket, MinecraftLoginPacket};
 
 /// ProxyHelloPacket is the first packet sent by the client to the proxy.
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -42,6 +43,8 @@ pub struct ProxyConnectedResponse {
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct ProxyClientJoinPacket {
     pub client_id: u16,
+    /// the player as sent in the Login Start packet, not set for status requests
+    pub login: Option<MinecraftLoginPacket>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -66,7 +69,7 @@ impl ProxyDataPacket {
         ProxyDataPacket {
             client_id,
             packet: MinecraftDataPacket {
-                data: packet.data.clone(),
+                data: Bytes::copy_from_slice(&packet.data),
             },
         }
     }
@@ -83,8 +86,8 @@ impl From<MinecraftDataPacket> for ProxyDataPacket {
 
 /// ProxyClientJoinPacket constructor
 impl ProxyClientJoinPacket {
-    pub fn new(client_id: u16) -> Self {
-        ProxyClientJoinPacket { client_id }
+    pub fn new(client_id: u16, login: Option<MinecraftLoginPacket>) -> Self {
+        ProxyClientJoinPacket { client_id, login }
     }
 }
 
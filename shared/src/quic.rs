@@ -0,0 +1,307 @@
+//! QUIC transport between the client and the relay.
+//!
+//! The client opens the first bidirectional stream of the connection and uses it like the
+//! TCP connection, the hello, the authentication and the control packets go through it in
+//! the same wire format. The relay opens a stream of its own for every player that joins
+//! and sends the `ProxyJoin`, the data and the `ProxyDisconnect` of the player through it,
+//! so a lost packet only stalls one player. The connection survives a change of the
+//! address of the client, e.g. when the IP of a home connection changes.
+//!
+//! The relay uses a self-signed certificate which it keeps across restarts. The client pins
+//! the fingerprint of the certificate, so nobody on the path can terminate the connection
+//! and relay the challenge of the key of the server. Without a pinned fingerprint the
+//! certificate of the first connection is trusted and pinned from then on.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{
+    ClientConfig, Connection, Endpoint, RecvStream, SendStream, ServerConfig, TransportConfig,
+};
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::config::MAXIMUM_CLIENTS;
+
+/// name of the relay in the certificate and the TLS handshake
+pub const SERVER_NAME: &str = "craftip";
+/// ALPN protocol of the tunnel
+const ALPN: &[u8] = b"craftip";
+/// keeps the NAT mapping of the client open and notices a dead connection
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// bidirectional QUIC stream that is used like a TCP connection
+#[derive(Debug)]
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicStream {
+    pub fn new((send, recv): (SendStream, RecvStream)) -> Self {
+        Self { send, recv }
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// self-signed certificate of the relay and its key, hex encoded DER
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayCertificate {
+    certificate: String,
+    key: String,
+}
+
+impl RelayCertificate {
+    pub fn generate() -> Result<Self> {
+        let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])?;
+        Ok(Self {
+            certificate: hex::encode(certificate.cert.der()),
+            key: hex::encode(certificate.key_pair.serialize_der()),
+        })
+    }
+    /// fingerprint the clients pin
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(fingerprint(&hex::decode(&self.certificate)?))
+    }
+}
+
+/// hex encoded SHA-256 hash of a DER encoded certificate
+pub fn fingerprint(certificate: &[u8]) -> String {
+    hex::encode(digest(&SHA256, certificate))
+}
+
+/// fingerprint of the certificate the relay presented on the connection
+pub fn peer_fingerprint(connection: &Connection) -> Option<String> {
+    let identity = connection.peer_identity()?;
+    let certificates = identity.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+    certificates
+        .first()
+        .map(|certificate| fingerprint(certificate))
+}
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// endpoint of the relay presenting `certificate`
+pub fn server_endpoint(addr: SocketAddr, certificate: &RelayCertificate) -> Result<Endpoint> {
+    let cert = CertificateDer::from(hex::decode(&certificate.certificate)?);
+    let key = PrivatePkcs8KeyDer::from(hex::decode(&certificate.key)?);
+    let mut tls = rustls::ServerConfig::builder_with_provider(crypto_provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key.into())?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut transport = TransportConfig::default();
+    // the client only opens the stream of the control packets
+    transport.max_concurrent_bidi_streams(1u8.into());
+    transport.max_concurrent_uni_streams(0u8.into());
+    let mut config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls)?));
+    config.transport_config(Arc::new(transport));
+    config.migration(true);
+    Ok(Endpoint::server(config, addr)?)
+}
+
+/// endpoint of the client for connections to the relay at `relay`, which has to present
+/// the certificate with the `pinned` fingerprint, any certificate if none is pinned yet
+pub fn client_endpoint(relay: SocketAddr, pinned: Option<&str>) -> Result<Endpoint> {
+    let pinned = match pinned {
+        Some(pinned) if hex::decode(pinned).map(|hash| hash.len()) != Ok(32) => {
+            bail!("invalid certificate fingerprint {}", pinned)
+        }
+        pinned => pinned.map(str::to_lowercase),
+    };
+    let verifier = PinnedServerVerification {
+        provider: crypto_provider(),
+        pinned,
+    };
+    let mut tls = rustls::ClientConfig::builder_with_provider(crypto_provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut transport = TransportConfig::default();
+    // the relay opens a stream for every player
+    transport.max_concurrent_bidi_streams((MAXIMUM_CLIENTS as u32 + 1).into());
+    transport.max_concurrent_uni_streams(0u8.into());
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    let mut config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls)?));
+    config.transport_config(Arc::new(transport));
+
+    let bind = match relay {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let mut endpoint = Endpoint::client(bind)?;
+    endpoint.set_default_client_config(config);
+    Ok(endpoint)
+}
+
+/// accepts the certificate with the pinned fingerprint instead of a chain of trust,
+/// and checks that the relay owns the key of it
+#[derive(Debug)]
+struct PinnedServerVerification {
+    provider: Arc<CryptoProvider>,
+    /// every certificate is accepted if not set, the client pins it afterwards
+    pinned: Option<String>,
+}
+
+impl ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match &self.pinned {
+            Some(pinned) if *pinned != fingerprint(end_entity) => Err(rustls::Error::General(
+                "the certificate of the relay does not match the pinned fingerprint".to_string(),
+            )),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_codec::PacketCodec;
+    use crate::socket_packet::SocketPacket;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::Framed;
+
+    #[tokio::test]
+    async fn test_quic_streams() {
+        let certificate = RelayCertificate::generate().unwrap();
+        let relay = server_endpoint("127.0.0.1:0".parse().unwrap(), &certificate).unwrap();
+        let addr = relay.local_addr().unwrap();
+        let client = client_endpoint(addr, None).unwrap();
+
+        let connecting = client.connect(addr, SERVER_NAME).unwrap();
+        let accepting = async { relay.accept().await.unwrap().await };
+        let (client_connection, relay_connection) = tokio::join!(connecting, accepting);
+        let (client_connection, relay_connection) =
+            (client_connection.unwrap(), relay_connection.unwrap());
+        assert_eq!(
+            peer_fingerprint(&client_connection),
+            certificate.fingerprint().ok()
+        );
+
+        // the client opens the control stream, the relay the stream of a player
+        let control = QuicStream::new(client_connection.open_bi().await.unwrap());
+        let mut control = Framed::new(control, PacketCodec::proxy(1024));
+        control.send(SocketPacket::ProxyPing(1)).await.unwrap();
+        let stream = QuicStream::new(relay_connection.accept_bi().await.unwrap());
+        let mut stream = Framed::new(stream, PacketCodec::proxy(1024));
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            SocketPacket::ProxyPing(1)
+        );
+
+        let player = QuicStream::new(relay_connection.open_bi().await.unwrap());
+        let mut player = Framed::new(player, PacketCodec::proxy(1024));
+        player.send(SocketPacket::ProxyDisconnect(3)).await.unwrap();
+        let accepted = QuicStream::new(client_connection.accept_bi().await.unwrap());
+        let mut accepted = Framed::new(accepted, PacketCodec::proxy(1024));
+        assert_eq!(
+            accepted.next().await.unwrap().unwrap(),
+            SocketPacket::ProxyDisconnect(3)
+        );
+        // the stream ends when the relay drops it
+        drop(player);
+        assert!(accepted.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_certificate() {
+        let certificate = RelayCertificate::generate().unwrap();
+        let relay = server_endpoint("127.0.0.1:0".parse().unwrap(), &certificate).unwrap();
+        let addr = relay.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(incoming) = relay.accept().await {
+                let _ = incoming.await;
+            }
+        });
+
+        let pinned = certificate.fingerprint().unwrap();
+        let client = client_endpoint(addr, Some(&pinned.to_uppercase())).unwrap();
+        assert!(client.connect(addr, SERVER_NAME).unwrap().await.is_ok());
+        // a relay with another certificate is refused
+        let other = RelayCertificate::generate().unwrap().fingerprint().unwrap();
+        let client = client_endpoint(addr, Some(&other)).unwrap();
+        assert!(client.connect(addr, SERVER_NAME).unwrap().await.is_err());
+        assert!(client_endpoint(addr, Some("00")).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// bytes a player may send per round before it is the turn of the next one
+pub const DEFAULT_QUANTUM: usize = 1024 * 4;
+/// packets up to this size, e.g. keepalives, are sent before the packets of busy players
+pub const SMALL_PACKET: usize = 64;
+
+/// Schedules the packets of the players sharing a tunnel.
+///
+/// Every player has a queue of its own and the queues take turns with deficit round robin,
+/// so a player downloading a lot of chunks can not delay the keepalives of everyone else.
+/// A player whose queue was empty and gets a small packet is served before the busy players
+/// for one quantum. The packets of a player keep their order.
+#[derive(Debug)]
+pub struct FairQueue<K, T> {
+    flows: HashMap<K, Flow<T>>,
+    /// players that just started sending with a small packet, served first
+    new_flows: VecDeque<K>,
+    /// players that are sending for longer, served in turns
+    old_flows: VecDeque<K>,
+    quantum: usize,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Flow<T> {
+    packets: VecDeque<(usize, T)>,
+    /// bytes the player may still send in this round
+    deficit: usize,
+}
+
+impl<K: Hash + Eq + Copy, T> Default for FairQueue<K, T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUANTUM)
+    }
+}
+
+impl<K: Hash + Eq + Copy, T> FairQueue<K, T> {
+    pub fn new(quantum: usize) -> Self {
+        Self {
+            flows: HashMap::new(),
+            new_flows: VecDeque::new(),
+            old_flows: VecDeque::new(),
+            quantum,
+            len: 0,
+        }
+    }
+    /// queues a packet of `size` bytes of the player `key`
+    pub fn push(&mut self, key: K, size: usize, item: T) {
+        let flow = self.flows.entry(key).or_insert_with(|| Flow {
+            packets: VecDeque::new(),
+            deficit: 0,
+        });
+        // a flow is scheduled as long as it has packets
+        if flow.packets.is_empty() {
+            flow.deficit = self.quantum;
+            if size <= SMALL_PACKET {
+                self.new_flows.push_back(key);
+            } else {
+                self.old_flows.push_back(key);
+            }
+        }
+        flow.packets.push_back((size, item));
+        self.len += 1;
+    }
+    /// the next packet to send and its player
+    pub fn pop(&mut self) -> Option<(K, T)> {
+        loop {
+            let (is_new, key) = match self.new_flows.front() {
+                Some(&key) => (true, key),
+                None => (false, *self.old_flows.front()?),
+            };
+            let flow = self
+                .flows
+                .get_mut(&key)
+                .expect("scheduled flow has packets");
+            let (size, _) = flow.packets.front().expect("scheduled flow has packets");
+            if flow.deficit < *size {
+                // the player has used up its share of this round
+                flow.deficit += self.quantum;
+                self.unschedule(is_new);
+                self.old_flows.push_back(key);
+                continue;
+            }
+            let (size, item) = flow.packets.pop_front().unwrap();
+            flow.deficit -= size;
+            if flow.packets.is_empty() {
+                self.flows.remove(&key);
+                self.unschedule(is_new);
+            }
+            self.len -= 1;
+            return Some((key, item));
+        }
+    }
+    fn unschedule(&mut self, is_new: bool) {
+        match is_new {
+            true => self.new_flows.pop_front(),
+            false => self.old_flows.pop_front(),
+        };
+    }
+    /// takes the queued packets of the player out of the queue
+    pub fn remove(&mut self, key: &K) -> Vec<T> {
+        let Some(flow) = self.flows.remove(key) else {
+            return Vec::new();
+        };
+        self.len -= flow.packets.len();
+        self.new_flows.retain(|flow| flow != key);
+        self.old_flows.retain(|flow| flow != key);
+        flow.packets.into_iter().map(|(_, item)| item).collect()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fair_queue() {
+        let mut queue = FairQueue::new(100);
+        // player 1 downloads chunks, player 2 sends a keepalive afterwards
+        for chunk in 0..4 {
+            queue.push(1u16, 100, chunk);
+        }
+        queue.push(2, 10, 10);
+        queue.push(3, 100, 20);
+        queue.push(3, 100, 21);
+        assert_eq!(queue.len(), 7);
+
+        assert_eq!(queue.pop(), Some((2, 10)));
+        assert_eq!(queue.pop(), Some((1, 0)));
+        assert_eq!(queue.pop(), Some((3, 20)));
+        assert_eq!(queue.pop(), Some((1, 1)));
+        assert_eq!(queue.pop(), Some((3, 21)));
+        queue.push(2, 10, 11);
+        assert_eq!(queue.pop(), Some((2, 11)));
+        assert_eq!(queue.remove(&1), [2, 3]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_large_packets() {
+        let mut queue = FairQueue::new(100);
+        queue.push(1u16, 250, 0);
+        queue.push(2, 100, 1);
+        queue.push(2, 100, 2);
+        queue.push(2, 100, 3);
+        // the large packet waits until the player has saved up enough rounds
+        assert_eq!(queue.pop(), Some((2, 1)));
+        assert_eq!(queue.pop(), Some((2, 2)));
+        assert_eq!(queue.pop(), Some((1, 0)));
+        assert_eq!(queue.pop(), Some((2, 3)));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -1,22 +1,37 @@
-use std::io::Write;
 use std::mem::size_of;
 use std::net::SocketAddr;
 
-use crate::crypto::{ChallengeDataType, SignatureDataType};
+use crate::addressing::DistributorError;
+use crate::admin::{PlayerInfo, TunnelInfo};
+use crate::compression::CompressionAlgorithm;
+use crate::crypto::{ChallengeDataType, KeyRotation, ServerPublicKey, SignatureDataType};
 use bytes::{Buf, BytesMut};
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
 
-use crate::cursor::{CustomCursor, CustomCursorMethods};
 use crate::datatypes::PacketError;
 use crate::datatypes::Protocol;
+use crate::minecraft::MinecraftLoginPacket;
 use crate::minecraft::{MinecraftDataPacket, MinecraftHelloPacket};
-use crate::proxy::{ProxyConnectedResponse, ProxyDataPacket, ProxyHelloPacket};
+use crate::player_filter::PlayerFilter;
+use crate::proxy::{
+    ProxyClientJoinPacket, ProxyConnectedResponse, ProxyDataPacket, ProxyHelloPacket,
+};
+use crate::wire;
+
+/// Sent by the client in front of the `ProxyHello`, so the relay recognizes proxy connections
+/// before parsing anything as minecraft packet. Minecraft packets never start with a length of
+/// zero and neither do packets of older clients. The last byte is the version of the preamble.
+pub const PROXY_PREAMBLE: &[u8] = b"\0\0craftip\x01";
+const PROXY_MAGIC_LENGTH: usize = PROXY_PREAMBLE.len() - 1;
 
 pub type PingPacket = u16;
 pub type ClientID = u16;
 
+/// Packets of minecraft and proxy connections. Proxy packets are sent in the format described in
+/// [`crate::wire`], the serde representation is only used for clients of the legacy format.
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub enum SocketPacket {
     MCHello(MinecraftHelloPacket),
@@ -27,7 +42,7 @@ pub enum SocketPacket {
     #[serde(with = "BigArray")]
     ProxyAuthResponse(SignatureDataType),
     ProxyHelloResponse(ProxyConnectedResponse),
-    ProxyJoin(ClientID),
+    ProxyJoin(ProxyClientJoinPacket),
     ProxyDisconnect(ClientID),
     ProxyDisconnectAck(ClientID),
     ProxyError(String),
@@ -35,6 +50,36 @@ pub enum SocketPacket {
     ProxyData(ProxyDataPacket),
     ProxyPing(PingPacket),
     ProxyPong(PingPacket),
+    /// asks the relay to route a custom domain to this tunnel
+    ProxyDomainRequest(String),
+    /// the relay verified the ownership of the custom domain
+    ProxyDomainVerified(String),
+    /// hands the hostname of the authenticated key over to a new key
+    ProxyKeyRotation(KeyRotation),
+    /// the relay accepted the key rotation, the old key is rejected from now on
+    ProxyKeyRotated(ServerPublicKey),
+    /// the relay does not accept new connections anymore, contains the seconds
+    /// until the remaining sessions are closed if the relay is shutting down
+    ProxyDraining(Option<u64>),
+    /// players the tunnel lets in, replaces the filter sent before
+    ProxyPlayerFilter(PlayerFilter),
+    /// compression algorithms the client supports, sent right after the hello
+    ProxyCompressionOffer(Vec<CompressionAlgorithm>),
+    /// the data packets are compressed with this algorithm from now on
+    ProxyCompressionSelected(CompressionAlgorithm),
+    /// data packet with compressed data, only seen by the codec, which hands out `ProxyData`
+    ProxyCompressedData(ProxyDataPacket),
+    /// connections the client wants to open for the tunnel, sent right after the hello
+    ProxyConnectionsRequest(u8),
+    /// connections the client may open for the tunnel, including the first one
+    ProxyConnectionsGranted(u8),
+    /// sent right after the hello of an extra connection, which joins the tunnel
+    /// of the hostname instead of replacing it
+    ProxyAttach,
+    /// players the client wants to let into the tunnel at once, sent right after the hello
+    ProxyPlayerLimitRequest(u16),
+    /// players the relay lets into the tunnel at once, more are turned away
+    ProxyPlayerLimitGranted(u16),
     Unknown,
 }
 
@@ -72,44 +117,115 @@ impl From<ProxyDataPacket> for SocketPacket {
     }
 }
 
+/// returns the length of the frame at the start of the buffer after checking that it arrived
+fn frame_length(buf: &BytesMut) -> Result<usize, PacketError> {
+    if buf.len() < size_of::<u16>() {
+        return Err(PacketError::TooSmall);
+    }
+    let length = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if buf.len() < size_of::<u16>() + length {
+        return Err(PacketError::TooSmall);
+    }
+    Ok(length)
+}
+
 impl SocketPacket {
+    /// encodes the packet in the wire format described in [`crate::wire`]
     pub fn encode(&self) -> Result<Vec<u8>, PacketError> {
-        let mut cursor = CustomCursor::new(vec![]);
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf)?;
+        Ok(buf.to_vec())
+    }
+    /// appends the packet in the wire format to the buffer
+    pub fn encode_into(&self, buf: &mut BytesMut) -> Result<(), PacketError> {
+        wire::encode_packet(self, buf)
+    }
+    /// whether clients of the legacy format know the packet, they close the connection on
+    /// packets that were added after the format
+    pub fn is_legacy(&self) -> bool {
+        matches!(
+            self,
+            SocketPacket::MCHello(_)
+                | SocketPacket::MCData(_)
+                | SocketPacket::ProxyHello(_)
+                | SocketPacket::ProxyAuthRequest(_)
+                | SocketPacket::ProxyAuthResponse(_)
+                | SocketPacket::ProxyHelloResponse(_)
+                | SocketPacket::ProxyJoin(_)
+                | SocketPacket::ProxyDisconnect(_)
+                | SocketPacket::ProxyDisconnectAck(_)
+                | SocketPacket::ProxyError(_)
+                | SocketPacket::ProxyData(_)
+                | SocketPacket::ProxyPing(_)
+                | SocketPacket::ProxyPong(_)
+        )
+    }
+    /// encodes the packet for clients that connect without the preamble
+    pub fn encode_legacy(&self) -> Result<Vec<u8>, PacketError> {
         let packet = bincode::serialize(self).map_err(|_| PacketError::EncodingError)?;
-        let packet_length = packet.len() as u16;
-        cursor
-            .write_all(&packet_length.to_be_bytes())
-            .expect("encoding error in write_all function");
-        cursor
-            .write_all(&packet)
-            .expect("encoding error in write_all function");
-        Ok(cursor.get_ref()[..cursor.position() as usize].to_vec())
+        let length = u16::try_from(packet.len()).map_err(|_| PacketError::EncodingError)?;
+        Ok([&length.to_be_bytes()[..], &packet].concat())
     }
 }
 
 impl SocketPacket {
+    /// decodes the next packet in the wire format, packets with unknown tags are skipped
     pub fn decode_proxy(buf: &mut BytesMut) -> Result<SocketPacket, PacketError> {
-        let mut cursor = CustomCursor::new(buf.to_vec());
-        cursor.throw_error_if_smaller(size_of::<u16>())?;
-        let length = cursor.get_u16();
-        cursor.throw_error_if_smaller(length as usize)?;
-        let result = bincode::deserialize::<SocketPacket>(
-            &cursor.get_ref()
-                [cursor.position() as usize..cursor.position() as usize + length as usize],
-        )
-        .map_err(|_| PacketError::NotValid)?;
-        buf.advance(cursor.position() as usize + length as usize);
-        // decode bincode packet
+        loop {
+            let length = frame_length(buf)?;
+            buf.advance(size_of::<u16>());
+            // the packet keeps the memory of the frame instead of copying it
+            let frame = buf.split_to(length).freeze();
+            let tag = frame.first().copied();
+            let packet = wire::decode_packet(frame)?;
+            if packet != SocketPacket::Unknown {
+                return Ok(packet);
+            }
+            tracing::debug!("skipping packet with unknown tag {:?}", tag);
+        }
+    }
+    /// decodes the next packet of a client that connected without the preamble
+    pub fn decode_legacy(buf: &mut BytesMut) -> Result<SocketPacket, PacketError> {
+        let length = frame_length(buf)?;
+        let packet = &buf[size_of::<u16>()..size_of::<u16>() + length];
+        let result =
+            bincode::deserialize::<SocketPacket>(packet).map_err(|_| PacketError::NotValid)?;
+        buf.advance(size_of::<u16>() + length);
         Ok(result)
     }
 }
 
 impl SocketPacket {
-    pub fn parse_first_package(packet: &mut BytesMut) -> Result<SocketPacket, PacketError> {
+    /// removes the proxy preamble from the start of the connection,
+    /// returns false if the connection does not start with it
+    pub fn strip_preamble(buf: &mut BytesMut) -> Result<bool, PacketError> {
+        let length = buf.len().min(PROXY_PREAMBLE.len());
+        if buf[..length] != PROXY_PREAMBLE[..length] {
+            // a preamble of a version this relay does not know
+            if length > PROXY_MAGIC_LENGTH
+                && buf[..PROXY_MAGIC_LENGTH] == PROXY_PREAMBLE[..PROXY_MAGIC_LENGTH]
+            {
+                return Err(PacketError::NotValidFirstPacket);
+            }
+            return Ok(false);
+        }
+        if length < PROXY_PREAMBLE.len() {
+            return Err(PacketError::TooSmall);
+        }
+        buf.advance(length);
+        Ok(true)
+    }
+    /// parses the first packet of a connection without preamble, if `legacy_proxy` is set
+    /// everything that is not a minecraft handshake is decoded as proxy packet like older
+    /// clients expect it
+    pub fn parse_first_package(
+        packet: &mut BytesMut,
+        legacy_proxy: bool,
+    ) -> Result<SocketPacket, PacketError> {
         match MinecraftHelloPacket::new(packet) {
             Ok(pkg) => Ok(SocketPacket::from(pkg)),
-            Err(PacketError::NotValid) => SocketPacket::decode_proxy(packet),
-            Err(PacketError::NotMatching) => SocketPacket::decode_proxy(packet),
+            Err(PacketError::NotMatching) if legacy_proxy => SocketPacket::decode_legacy(packet),
+            Err(PacketError::NotMatching) => Err(PacketError::NotValidFirstPacket),
             Err(e) => Err(e),
         }
     }
@@ -121,6 +237,7 @@ impl SocketPacket {
         match protocol {
             Protocol::MC(_) => MinecraftDataPacket::new(buf).map(SocketPacket::from),
             Protocol::Proxy(_) => SocketPacket::decode_proxy(buf),
+            Protocol::LegacyProxy(_) => SocketPacket::decode_legacy(buf),
             _ => {
                 unimplemented!()
             }
@@ -134,7 +251,28 @@ impl SocketPacket {
 #[derive(Debug)]
 pub enum ClientToProxy {
     Packet(SocketAddr, MinecraftDataPacket),
-    AddMinecraftClient(SocketAddr, UnboundedSender<MinecraftDataPacket>),
+    /// a player joined, the flag is set if the player uses the protocol from before 1.7
+    AddMinecraftClient(
+        SocketAddr,
+        UnboundedSender<MinecraftDataPacket>,
+        Option<MinecraftLoginPacket>,
+        bool,
+    ),
     RemoveMinecraftClient(SocketAddr),
     Close,
+    /// asks the proxy client for the state of the tunnel and its players
+    Info(oneshot::Sender<(TunnelInfo, Vec<PlayerInfo>)>),
+    /// disconnects a player, responds with false if there is no player with this id
+    Kick(ClientID, oneshot::Sender<bool>),
+    /// tells the proxy client that the relay is draining
+    Draining(Option<u64>),
+    /// an extra connection of the tunnel has been authenticated, the packets for it are
+    /// sent to the channel
+    AddLane(SocketAddr, UnboundedSender<SocketPacket>),
+    /// packet received on an extra connection
+    LanePacket(SocketAddr, SocketPacket),
+    RemoveLane(SocketAddr),
+    /// the TXT record of the requested domain has been looked up, contains the normalized
+    /// domain if the ownership has been proven
+    DomainVerified(String, Result<String, DistributorError>),
 }
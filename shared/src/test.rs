@@ -1,9 +1,22 @@
 #[cfg(test)]
 mod tests {
     use bytes::{BufMut, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
 
-    use crate::datatypes::get_varint;
-    use crate::minecraft::MinecraftHelloPacket;
+    use crate::compression::CompressionAlgorithm;
+    use crate::crypto::{ServerPrivateKey, ServerPublicKey};
+
+    use crate::datatypes::{get_varint, put_varint, PacketError};
+    use crate::minecraft::{
+        FirstPacket, MinecraftDataPacket, MinecraftHelloPacket, MinecraftLoginPacket,
+    };
+    use crate::packet_codec::{PacketCodec, PacketCodecError};
+    use crate::player_filter::{PlayerFilter, PlayerList};
+    use crate::proxy::{
+        ProxyAuthenticator, ProxyClientJoinPacket, ProxyConnectedResponse, ProxyDataPacket,
+        ProxyHelloPacket,
+    };
+    use crate::socket_packet::{SocketPacket, PROXY_PREAMBLE};
 
     struct TestHelloPacket {
         name: String,
@@ -15,9 +28,8 @@ mod tests {
         value: (i32, usize),
     }
 
-    #[test]
-    fn test_hello_packet_ping() {
-        let test_vector = vec![
+    fn hello_packets() -> Vec<TestHelloPacket> {
+        vec![
             TestHelloPacket {
                 name: "ping with long hostname".to_string(),
                 packet: MinecraftHelloPacket {
@@ -28,6 +40,7 @@ mod tests {
                         .parse()
                         .unwrap(),
                     port: 25565,
+                    username: None,
                     data: vec![
                         254, 1, 250, 0, 11, 0, 77, 0, 67, 0, 124, 0, 80, 0, 105, 0, 110, 0, 103, 0,
                         72, 0, 111, 0, 115, 0, 116, 0, 133, 73, 0, 63, 0, 97, 0, 97, 0, 97, 0, 97,
@@ -48,6 +61,7 @@ mod tests {
                     version: 73,
                     hostname: "hi".parse().unwrap(),
                     port: 25565,
+                    username: None,
                     data: vec![
                         254, 1, 250, 0, 11, 0, 77, 0, 67, 0, 124, 0, 80, 0, 105, 0, 110, 0, 103, 0,
                         72, 0, 111, 0, 115, 0, 116, 0, 11, 73, 0, 2, 0, 104, 0, 105, 0, 0, 99, 221,
@@ -64,6 +78,7 @@ mod tests {
                         .parse()
                         .unwrap(),
                     port: 25565,
+                    username: Some("PennerQueen".to_string()),
                     data: vec![
                         2, 73, 0, 11, 0, 80, 0, 101, 0, 110, 0, 110, 0, 101, 0, 114, 0, 81, 0, 117,
                         0, 101, 0, 101, 0, 110, 0, 63, 0, 97, 0, 97, 0, 97, 0, 97, 0, 97, 0, 97, 0,
@@ -84,6 +99,7 @@ mod tests {
                     version: 73,
                     hostname: "localhost".parse().unwrap(),
                     port: 25565,
+                    username: Some("PennerQueen".to_string()),
                     data: vec![
                         2, 73, 0, 11, 0, 80, 0, 101, 0, 110, 0, 110, 0, 101, 0, 114, 0, 81, 0, 117,
                         0, 101, 0, 101, 0, 110, 0, 9, 0, 108, 0, 111, 0, 99, 0, 97, 0, 108, 0, 104,
@@ -99,6 +115,7 @@ mod tests {
                     version: 73,
                     hostname: "localhost".parse().unwrap(),
                     port: 25565,
+                    username: Some("PennerQueen".to_string()),
                     data: vec![
                         2, 73, 0, 11, 0, 80, 0, 101, 0, 110, 0, 110, 0, 101, 0, 114, 0, 81, 0, 117,
                         0, 101, 0, 101, 0, 110, 0, 9, 0, 108, 0, 111, 0, 99, 0, 97, 0, 108, 0, 104,
@@ -114,13 +131,18 @@ mod tests {
                     version: 761,
                     hostname: "localhost".parse().unwrap(),
                     port: 25565,
+                    username: None,
                     data: vec![
                         16, 0, 249, 5, 9, 108, 111, 99, 97, 108, 104, 111, 115, 116, 99, 221,
                     ],
                 },
             },
-        ];
-        test_vector.iter().for_each(|test| {
+        ]
+    }
+
+    #[test]
+    fn test_hello_packet_ping() {
+        hello_packets().iter().for_each(|test| {
             println!("Testing {}...", test.name);
             let mut buf = BytesMut::with_capacity(1024);
             buf.put_slice(&test.packet.data);
@@ -130,6 +152,494 @@ mod tests {
         });
     }
 
+    /// feeds the packet byte by byte like a connection delivering one byte per segment
+    fn decode_byte_by_byte(mut codec: PacketCodec, data: &[u8]) -> SocketPacket {
+        let mut buf = BytesMut::new();
+        for (i, byte) in data.iter().enumerate() {
+            buf.put_u8(*byte);
+            let packet = codec.decode(&mut buf).unwrap();
+            if i + 1 < data.len() {
+                assert_eq!(
+                    packet,
+                    None,
+                    "decoded after {} of {} bytes",
+                    i + 1,
+                    data.len()
+                );
+            } else {
+                return packet.expect("not decoded after the last byte");
+            }
+        }
+        panic!("empty packet");
+    }
+
+    #[test]
+    fn test_hello_packet_byte_by_byte() {
+        for test in hello_packets() {
+            println!("Testing {}...", test.name);
+            let packet = decode_byte_by_byte(PacketCodec::new(1024 * 8), &test.packet.data);
+            assert_eq!(packet, SocketPacket::MCHello(test.packet));
+        }
+
+        // handshake with a length of two bytes
+        let hostname = "a".repeat(200);
+        let mut packet = vec![0x00];
+        put_varint(&mut packet, 763);
+        put_varint(&mut packet, hostname.len() as i32);
+        packet.extend(hostname.as_bytes());
+        packet.extend(25565u16.to_be_bytes());
+        packet.push(2);
+        let mut data = Vec::new();
+        put_varint(&mut data, packet.len() as i32);
+        data.extend(packet);
+        // the next state is not part of the hello packet
+        match decode_byte_by_byte(PacketCodec::new(1024 * 8), &data[..data.len() - 1]) {
+            SocketPacket::MCHello(hello) => {
+                assert_eq!(hello.hostname, hostname);
+                assert_eq!(hello.version, 763);
+                assert_eq!(hello.port, 25565);
+            }
+            packet => panic!("wrong packet {:?}", packet),
+        }
+
+        let hello = proxy_hello();
+        let data = encode(hello.clone());
+        assert_eq!(
+            decode_byte_by_byte(PacketCodec::new(1024 * 8), &data),
+            hello
+        );
+        let codec = PacketCodec::new(1024 * 8).legacy_proxy(true);
+        assert_eq!(
+            decode_byte_by_byte(codec, &hello.encode_legacy().unwrap()),
+            hello
+        );
+    }
+
+    fn proxy_hello() -> SocketPacket {
+        SocketPacket::from(ProxyHelloPacket {
+            version: 1,
+            hostname: "test.craftip.net".to_string(),
+            auth: ProxyAuthenticator::PublicKey(ServerPrivateKey::default().get_public_key()),
+        })
+    }
+
+    fn encode(packet: SocketPacket) -> BytesMut {
+        let mut buf = BytesMut::new();
+        PacketCodec::new(1024 * 8).encode(packet, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_proxy_preamble() {
+        // no minecraft handshake is taken for a proxy connection
+        for test in hello_packets() {
+            println!("Testing {}...", test.name);
+            let mut buf = BytesMut::from(&test.packet.data[..]);
+            assert_eq!(SocketPacket::strip_preamble(&mut buf), Ok(false));
+            for legacy_proxy in [false, true] {
+                let mut codec = PacketCodec::new(1024 * 8).legacy_proxy(legacy_proxy);
+                let mut buf = BytesMut::from(&test.packet.data[..]);
+                let packet = codec.decode(&mut buf).unwrap();
+                assert_eq!(packet, Some(SocketPacket::MCHello(test.packet.clone())));
+            }
+        }
+
+        // packets of older clients are only accepted in the compatibility mode
+        let mut buf = BytesMut::from(&proxy_hello().encode_legacy().unwrap()[..]);
+        let result = PacketCodec::new(1024 * 8).decode(&mut buf);
+        assert!(matches!(
+            result,
+            Err(PacketCodecError::PacketCodec(
+                PacketError::NotValidFirstPacket
+            ))
+        ));
+
+        // the preamble is followed by the hello
+        let mut buf = BytesMut::from(PROXY_PREAMBLE);
+        buf.extend(SocketPacket::ProxyPing(1).encode().unwrap());
+        let result = PacketCodec::new(1024 * 8).decode(&mut buf);
+        assert!(matches!(
+            result,
+            Err(PacketCodecError::PacketCodec(
+                PacketError::NotValidFirstPacket
+            ))
+        ));
+
+        // a preamble of an other version
+        let mut buf = encode(proxy_hello());
+        buf[PROXY_PREAMBLE.len() - 1] = 2;
+        assert_eq!(
+            SocketPacket::strip_preamble(&mut buf),
+            Err(PacketError::NotValidFirstPacket)
+        );
+
+        // the packets after the hello are proxy packets
+        let mut codec = PacketCodec::new(1024 * 8);
+        let hello = proxy_hello();
+        let mut buf = encode(hello.clone());
+        buf.extend(SocketPacket::ProxyPing(1).encode().unwrap());
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(hello));
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(SocketPacket::ProxyPing(1))
+        );
+
+        // random bytes never get to the proxy decoder
+        for _ in 0..1000 {
+            let size = rand::random::<usize>() % 1024;
+            let mut buf: BytesMut = (0..size).map(|_| rand::random::<u8>()).collect();
+            if let Ok(Some(packet)) = PacketCodec::new(1024 * 8).decode(&mut buf) {
+                assert!(matches!(packet, SocketPacket::MCHello(_)), "{:?}", packet);
+            }
+        }
+    }
+
+    /// locks the wire format down, see `crate::wire`
+    #[test]
+    fn test_wire_format() {
+        let key: ServerPublicKey = bincode::deserialize(&[7; 32]).unwrap();
+        let fixtures = vec![
+            (SocketPacket::ProxyPing(0x1234), "0003 0a 3412".to_string()),
+            (SocketPacket::ProxyPong(0x1234), "0003 0b 3412".to_string()),
+            (SocketPacket::ProxyDisconnect(7), "0003 06 0700".to_string()),
+            (
+                SocketPacket::ProxyDisconnectAck(7),
+                "0003 07 0700".to_string(),
+            ),
+            (
+                SocketPacket::ProxyError("no".to_string()),
+                "000b 08 0200000000000000 6e6f".to_string(),
+            ),
+            (SocketPacket::ProxyDraining(None), "0002 10 00".to_string()),
+            (
+                SocketPacket::ProxyPlayerFilter(PlayerFilter {
+                    whitelist: None,
+                    blacklist: PlayerList {
+                        usernames: vec!["Notch".to_string()],
+                        networks: Vec::new(),
+                    },
+                    message: Some("no".to_string()),
+                }),
+                format!(
+                    "002a 11 00 {} {} 4e6f746368 {} 01 {} 6e6f",
+                    "0100000000000000", "0500000000000000", "0000000000000000", "0200000000000000"
+                ),
+            ),
+            (
+                SocketPacket::ProxyDraining(Some(30)),
+                "000a 10 01 1e00000000000000".to_string(),
+            ),
+            (
+                SocketPacket::ProxyCompressionOffer(vec![CompressionAlgorithm::Deflate]),
+                "000d 12 0100000000000000 00000000".to_string(),
+            ),
+            (
+                SocketPacket::ProxyCompressionSelected(CompressionAlgorithm::Deflate),
+                "0005 13 00000000".to_string(),
+            ),
+            // the compressed data is not length prefixed, it fills the rest of the frame
+            (
+                SocketPacket::ProxyCompressedData(ProxyDataPacket::new(
+                    MinecraftDataPacket {
+                        data: vec![1, 2, 3].into(),
+                    },
+                    2,
+                )),
+                "0006 14 0200 010203".to_string(),
+            ),
+            (
+                SocketPacket::ProxyConnectionsRequest(3),
+                "0002 15 03".to_string(),
+            ),
+            (
+                SocketPacket::ProxyConnectionsGranted(2),
+                "0002 16 02".to_string(),
+            ),
+            (SocketPacket::ProxyAttach, "0001 17".to_string()),
+            (
+                SocketPacket::ProxyPlayerLimitRequest(300),
+                "0003 18 2c01".to_string(),
+            ),
+            (
+                SocketPacket::ProxyPlayerLimitGranted(256),
+                "0003 19 0001".to_string(),
+            ),
+            (
+                SocketPacket::from(ProxyConnectedResponse { version: 1 }),
+                "0003 04 0100".to_string(),
+            ),
+            (
+                SocketPacket::ProxyJoin(ProxyClientJoinPacket::new(
+                    1,
+                    Some(MinecraftLoginPacket {
+                        name: "Notch".to_string(),
+                        uuid: None,
+                    }),
+                )),
+                "0012 05 0100 01 0500000000000000 4e6f746368 00".to_string(),
+            ),
+            (
+                SocketPacket::from(ProxyDataPacket::new(
+                    MinecraftDataPacket {
+                        data: vec![1, 2, 3].into(),
+                    },
+                    2,
+                )),
+                "000e 09 0200 0300000000000000 010203".to_string(),
+            ),
+            (
+                SocketPacket::from(ProxyHelloPacket {
+                    version: 1,
+                    hostname: "a.b".to_string(),
+                    auth: ProxyAuthenticator::PublicKey(key.clone()),
+                }),
+                format!(
+                    "0032 01 0100 0300000000000000 612e62 00000000 {}",
+                    "07".repeat(32)
+                ),
+            ),
+            (
+                SocketPacket::ProxyKeyRotated(key),
+                format!("0021 0f {}", "07".repeat(32)),
+            ),
+            (
+                SocketPacket::ProxyAuthRequest([9; 64]),
+                format!("0041 02 {}", "09".repeat(64)),
+            ),
+            (
+                SocketPacket::ProxyAuthResponse([9; 64]),
+                format!("0041 03 {}", "09".repeat(64)),
+            ),
+        ];
+        for (packet, fixture) in fixtures {
+            let fixture = hex::decode(fixture.replace(' ', "")).unwrap();
+            assert_eq!(packet.encode().unwrap(), fixture, "{:?}", packet);
+            let mut buf = BytesMut::from(&fixture[..]);
+            assert_eq!(SocketPacket::decode_proxy(&mut buf).unwrap(), packet);
+            assert!(buf.is_empty());
+        }
+
+        // packets with unknown tags are skipped
+        let mut buf =
+            BytesMut::from(&hex::decode("0003 63 aabb 0003 0a 3412".replace(' ', "")).unwrap()[..]);
+        assert_eq!(
+            SocketPacket::decode_proxy(&mut buf).unwrap(),
+            SocketPacket::ProxyPing(0x1234)
+        );
+        let mut buf = BytesMut::from(&hex::decode("0003 63 aabb 00".replace(' ', "")).unwrap()[..]);
+        assert_eq!(
+            SocketPacket::decode_proxy(&mut buf),
+            Err(PacketError::TooSmall)
+        );
+        assert_eq!(buf.len(), 1);
+
+        // a known tag with a broken payload is an error
+        let mut buf = BytesMut::from(&hex::decode("00020a34").unwrap()[..]);
+        assert_eq!(
+            SocketPacket::decode_proxy(&mut buf),
+            Err(PacketError::NotValid)
+        );
+
+        // minecraft packets are never sent as proxy packets
+        let packet = SocketPacket::from(MinecraftDataPacket {
+            data: vec![1].into(),
+        });
+        assert_eq!(packet.encode(), Err(PacketError::EncodingError));
+    }
+
+    #[test]
+    fn test_proxy_data_without_copy() {
+        let packet = SocketPacket::from(ProxyDataPacket::new(
+            MinecraftDataPacket {
+                data: vec![7; 1000].into(),
+            },
+            3,
+        ));
+        let mut buf = BytesMut::new();
+        packet.encode_into(&mut buf).unwrap();
+        let received = buf.as_ptr_range();
+        match SocketPacket::decode_proxy(&mut buf).unwrap() {
+            SocketPacket::ProxyData(decoded) => {
+                assert_eq!(SocketPacket::ProxyData(decoded.clone()), packet);
+                // the data points into the received buffer
+                let data = decoded.packet.data.as_ptr_range();
+                assert!(received.start <= data.start && data.end <= received.end);
+            }
+            packet => panic!("wrong packet {:?}", packet),
+        }
+
+        let mut buf = BytesMut::from(&[1, 2, 3][..]);
+        let received = buf.as_ptr();
+        let packet = MinecraftDataPacket::new(&mut buf).unwrap();
+        assert_eq!(packet.data.as_ptr(), received);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_legacy_proxy() {
+        let mut codec = PacketCodec::new(1024 * 8).legacy_proxy(true);
+        let hello = proxy_hello();
+        let mut buf = BytesMut::from(&hello.encode_legacy().unwrap()[..]);
+        buf.extend(SocketPacket::ProxyPing(1).encode_legacy().unwrap());
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(hello));
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(SocketPacket::ProxyPing(1))
+        );
+        // the relay answers in the format the client used
+        let mut buf = BytesMut::new();
+        codec.encode(SocketPacket::ProxyPong(1), &mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &SocketPacket::ProxyPong(1).encode_legacy().unwrap()[..]
+        );
+        // packets added later would close the connection of the client
+        let mut buf = BytesMut::new();
+        codec
+            .encode(SocketPacket::ProxyDraining(Some(30)), &mut buf)
+            .unwrap();
+        codec
+            .encode(SocketPacket::ProxyPlayerLimitGranted(10), &mut buf)
+            .unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_compressed_proxy_data() {
+        let mut client = PacketCodec::proxy(1024 * 8);
+        let mut relay = PacketCodec::proxy(1024 * 8);
+        let packet = SocketPacket::from(ProxyDataPacket::new(
+            MinecraftDataPacket {
+                data: vec![7; 1000].into(),
+            },
+            3,
+        ));
+        let mut buf = BytesMut::new();
+        client.encode(packet.clone(), &mut buf).unwrap();
+        let uncompressed = buf.len();
+        relay.decode(&mut buf).unwrap();
+
+        client.set_compression(CompressionAlgorithm::Deflate);
+        relay.set_compression(CompressionAlgorithm::Deflate);
+        for _ in 0..3 {
+            client.encode(packet.clone(), &mut buf).unwrap();
+            // other packets are not compressed
+            client.encode(SocketPacket::ProxyPing(1), &mut buf).unwrap();
+        }
+        assert!(buf.len() < uncompressed);
+        for _ in 0..3 {
+            assert_eq!(relay.decode(&mut buf).unwrap(), Some(packet.clone()));
+            assert_eq!(
+                relay.decode(&mut buf).unwrap(),
+                Some(SocketPacket::ProxyPing(1))
+            );
+        }
+        let (sent, _) = client.compression_stats().unwrap();
+        let (_, received) = relay.compression_stats().unwrap();
+        assert_eq!(sent, received);
+        assert_eq!(sent.raw, 3000);
+        assert!(sent.ratio() < 0.1);
+
+        // compressed data is only accepted after the compression was selected
+        client.encode(packet.clone(), &mut buf).unwrap();
+        assert!(PacketCodec::proxy(1024 * 8).decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_first_packet_detection() {
+        assert_eq!(FirstPacket::detect(&[]), Err(PacketError::TooSmall));
+        assert_eq!(FirstPacket::detect(&[0xFE]), Err(PacketError::TooSmall));
+        assert_eq!(
+            FirstPacket::detect(&[0xFE, 0x01]),
+            Ok(FirstPacket::LegacyPing)
+        );
+        assert_eq!(FirstPacket::detect(&[0x02]), Ok(FirstPacket::LegacyConnect));
+        assert_eq!(FirstPacket::detect(&[0x10]), Err(PacketError::TooSmall));
+        assert_eq!(
+            FirstPacket::detect(&[0x10, 0x00]),
+            Ok(FirstPacket::Handshake)
+        );
+        assert_eq!(FirstPacket::detect(&[0xD0]), Err(PacketError::TooSmall));
+        assert_eq!(
+            FirstPacket::detect(&[0xD0, 0x01, 0x00]),
+            Ok(FirstPacket::Handshake)
+        );
+        // the length of a proxy packet is two bytes in big endian
+        assert_eq!(FirstPacket::detect(&[0x00, 0x40]), Ok(FirstPacket::Proxy));
+        assert_eq!(FirstPacket::detect(&[0x10, 0x05]), Ok(FirstPacket::Proxy));
+        assert_eq!(
+            FirstPacket::detect(&[0xFF, 0xFF, 0x7F]),
+            Ok(FirstPacket::Proxy)
+        );
+    }
+
+    #[test]
+    fn test_login_packet() {
+        let uuid = [
+            0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x47, 0x26, 0xa5, 0xbe, 0xfc, 0xa9, 0x0e, 0x38,
+            0xaa, 0xf5,
+        ];
+        // next state, length, id and name
+        let name = [2, 7, 0, 5, b'N', b'o', b't', b'c', b'h'];
+        let notch = |uuid: bool| MinecraftLoginPacket {
+            name: "Notch".to_string(),
+            uuid: uuid.then(|| "069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string()),
+        };
+
+        // 1.12.2 sends only the name
+        assert_eq!(MinecraftLoginPacket::new(&name, 340), Ok(notch(false)));
+        // 1.20.1 tells if the uuid follows
+        let mut packet = name.to_vec();
+        packet[1] += 17;
+        packet.push(1);
+        packet.extend(uuid);
+        assert_eq!(MinecraftLoginPacket::new(&packet, 763), Ok(notch(true)));
+        // 1.20.2 always sends the uuid, the data of the next packet is ignored
+        let mut packet = name.to_vec();
+        packet[1] += 16;
+        packet.extend(uuid);
+        packet.extend([1, 2, 3]);
+        assert_eq!(MinecraftLoginPacket::new(&packet, 764), Ok(notch(true)));
+        // 1.19.2 without signature data
+        let mut packet = name.to_vec();
+        packet[1] += 18;
+        packet.extend([0, 1]);
+        packet.extend(uuid);
+        assert_eq!(MinecraftLoginPacket::new(&packet, 760), Ok(notch(true)));
+
+        // incomplete packets
+        for end in 0..name.len() {
+            assert_eq!(
+                MinecraftLoginPacket::new(&name[..end], 340),
+                Err(PacketError::TooSmall),
+                "{} bytes",
+                end
+            );
+        }
+        // status request
+        assert_eq!(
+            MinecraftLoginPacket::new(&[1, 1, 0], 340),
+            Err(PacketError::NotMatching)
+        );
+        // name too long
+        let mut packet = vec![2, 19, 0, 17];
+        packet.extend([b'a'; 17]);
+        assert_eq!(
+            MinecraftLoginPacket::new(&packet, 340),
+            Err(PacketError::NotValid)
+        );
+    }
+
+    #[test]
+    fn test_disconnect_packet() {
+        let packet = MinecraftDataPacket::disconnect("bye", false);
+        let reason = br#"{"text":"bye"}"#;
+        assert_eq!(packet.data[..3], [16, 0, 14]);
+        assert_eq!(packet.data[3..], reason[..]);
+        let packet = MinecraftDataPacket::disconnect("bye", true);
+        assert_eq!(packet.data[..], [0xFF, 0, 3, 0, b'b', 0, b'y', 0, b'e']);
+    }
+
     #[test]
     fn test_varint() {
         let test_vector = vec![
@@ -166,6 +676,9 @@ mod tests {
             println!("Testing {:?}...", test.value);
             let value = get_varint(&*test.buffer.clone(), 0).unwrap();
             assert_eq!(value, test.value);
+            let mut buffer = Vec::new();
+            put_varint(&mut buffer, test.value.0);
+            assert_eq!(buffer, test.buffer);
         });
     }
 
@@ -0,0 +1,233 @@
+//! Wire format of the packets between the client and the relay.
+//!
+//! A proxy connection starts with the [`PROXY_PREAMBLE`](crate::socket_packet::PROXY_PREAMBLE)
+//! followed by the packets, each of them framed as
+//!
+//! ```text
+//! length: u16 (big endian) | tag: u8 | payload: [u8; length - 1]
+//! ```
+//!
+//! The length counts the tag and the payload. The tag says which packet follows, the payload
+//! is the bincode encoding (little endian, fixed size integers, `u64` lengths) of the content
+//! of the packet, except for the challenge and the signature of the authentication which are
+//! sent as their 64 raw bytes.
+//!
+//! | tag | packet                |
+//! |-----|-----------------------|
+//! | 1   | `ProxyHello`          |
+//! | 2   | `ProxyAuthRequest`    |
+//! | 3   | `ProxyAuthResponse`   |
+//! | 4   | `ProxyHelloResponse`  |
+//! | 5   | `ProxyJoin`           |
+//! | 6   | `ProxyDisconnect`     |
+//! | 7   | `ProxyDisconnectAck`  |
+//! | 8   | `ProxyError`          |
+//! | 9   | `ProxyData`           |
+//! | 10  | `ProxyPing`           |
+//! | 11  | `ProxyPong`           |
+//! | 12  | `ProxyDomainRequest`  |
+//! | 13  | `ProxyDomainVerified` |
+//! | 14  | `ProxyKeyRotation`    |
+//! | 15  | `ProxyKeyRotated`     |
+//! | 16  | `ProxyDraining`       |
+//! | 17  | `ProxyPlayerFilter`   |
+//! | 18  | `ProxyCompressionOffer`    |
+//! | 19  | `ProxyCompressionSelected` |
+//! | 20  | `ProxyCompressedData`      |
+//! | 21  | `ProxyConnectionsRequest`  |
+//! | 22  | `ProxyConnectionsGranted`  |
+//! | 23  | `ProxyAttach`              |
+//! | 24  | `ProxyPlayerLimitRequest`  |
+//! | 25  | `ProxyPlayerLimitGranted`  |
+//!
+//! The payload of `ProxyCompressedData` is the client id followed by the compressed data up
+//! to the end of the frame, see [`Compressor`](crate::compression::Compressor).
+//!
+//! Tags are never reused and the payload of a tag never changes, a packet that needs other
+//! fields gets a new tag. Packets with a tag the receiver does not know are skipped, so a
+//! peer can send new packets to older peers as long as they are optional.
+//!
+//! Clients connecting without the preamble use the legacy format, the bincode encoding of the
+//! whole `SocketPacket` enum after the length. They only get the packets up to `ProxyPong`.
+
+use std::mem::size_of;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+use crate::datatypes::PacketError;
+use crate::minecraft::MinecraftDataPacket;
+use crate::proxy::ProxyDataPacket;
+use crate::socket_packet::SocketPacket;
+
+const PROXY_HELLO: u8 = 1;
+const PROXY_AUTH_REQUEST: u8 = 2;
+const PROXY_AUTH_RESPONSE: u8 = 3;
+const PROXY_HELLO_RESPONSE: u8 = 4;
+const PROXY_JOIN: u8 = 5;
+const PROXY_DISCONNECT: u8 = 6;
+const PROXY_DISCONNECT_ACK: u8 = 7;
+const PROXY_ERROR: u8 = 8;
+const PROXY_DATA: u8 = 9;
+const PROXY_PING: u8 = 10;
+const PROXY_PONG: u8 = 11;
+const PROXY_DOMAIN_REQUEST: u8 = 12;
+const PROXY_DOMAIN_VERIFIED: u8 = 13;
+const PROXY_KEY_ROTATION: u8 = 14;
+const PROXY_KEY_ROTATED: u8 = 15;
+const PROXY_DRAINING: u8 = 16;
+const PROXY_PLAYER_FILTER: u8 = 17;
+const PROXY_COMPRESSION_OFFER: u8 = 18;
+const PROXY_COMPRESSION_SELECTED: u8 = 19;
+const PROXY_COMPRESSED_DATA: u8 = 20;
+const PROXY_CONNECTIONS_REQUEST: u8 = 21;
+const PROXY_CONNECTIONS_GRANTED: u8 = 22;
+const PROXY_ATTACH: u8 = 23;
+const PROXY_PLAYER_LIMIT_REQUEST: u8 = 24;
+const PROXY_PLAYER_LIMIT_GRANTED: u8 = 25;
+
+/// writes the payload with bincode straight into the buffer
+fn put<T: Serialize>(buf: &mut BytesMut, tag: u8, value: &T) -> Result<(), PacketError> {
+    buf.put_u8(tag);
+    bincode::serialize_into(buf.writer(), value).map_err(|_| PacketError::EncodingError)
+}
+
+fn deserialize<'a, T: Deserialize<'a>>(payload: &'a [u8]) -> Result<T, PacketError> {
+    bincode::deserialize(payload).map_err(|_| PacketError::NotValid)
+}
+
+/// appends the frame of the packet to the buffer,
+/// minecraft packets and `Unknown` are never sent to a proxy connection
+pub fn encode_packet(packet: &SocketPacket, buf: &mut BytesMut) -> Result<(), PacketError> {
+    let start = buf.len();
+    // the length is filled in once the payload is written
+    buf.put_u16(0);
+    let result = match packet {
+        SocketPacket::ProxyHello(packet) => put(buf, PROXY_HELLO, packet),
+        SocketPacket::ProxyAuthRequest(challenge) => {
+            buf.put_u8(PROXY_AUTH_REQUEST);
+            buf.put_slice(challenge);
+            Ok(())
+        }
+        SocketPacket::ProxyAuthResponse(signature) => {
+            buf.put_u8(PROXY_AUTH_RESPONSE);
+            buf.put_slice(signature);
+            Ok(())
+        }
+        SocketPacket::ProxyHelloResponse(packet) => put(buf, PROXY_HELLO_RESPONSE, packet),
+        SocketPacket::ProxyJoin(packet) => put(buf, PROXY_JOIN, packet),
+        SocketPacket::ProxyDisconnect(id) => put(buf, PROXY_DISCONNECT, id),
+        SocketPacket::ProxyDisconnectAck(id) => put(buf, PROXY_DISCONNECT_ACK, id),
+        SocketPacket::ProxyError(message) => put(buf, PROXY_ERROR, message),
+        // same layout as bincode, but the data is copied only once
+        SocketPacket::ProxyData(packet) => {
+            buf.put_u8(PROXY_DATA);
+            buf.put_u16_le(packet.client_id);
+            buf.put_u64_le(packet.packet.data.len() as u64);
+            buf.put_slice(&packet.packet.data);
+            Ok(())
+        }
+        SocketPacket::ProxyPing(ping) => put(buf, PROXY_PING, ping),
+        SocketPacket::ProxyPong(ping) => put(buf, PROXY_PONG, ping),
+        SocketPacket::ProxyDomainRequest(domain) => put(buf, PROXY_DOMAIN_REQUEST, domain),
+        SocketPacket::ProxyDomainVerified(domain) => put(buf, PROXY_DOMAIN_VERIFIED, domain),
+        SocketPacket::ProxyKeyRotation(rotation) => put(buf, PROXY_KEY_ROTATION, rotation),
+        SocketPacket::ProxyKeyRotated(key) => put(buf, PROXY_KEY_ROTATED, key),
+        SocketPacket::ProxyDraining(timeout) => put(buf, PROXY_DRAINING, timeout),
+        SocketPacket::ProxyPlayerFilter(filter) => put(buf, PROXY_PLAYER_FILTER, filter),
+        SocketPacket::ProxyCompressionOffer(offer) => put(buf, PROXY_COMPRESSION_OFFER, offer),
+        SocketPacket::ProxyCompressionSelected(algorithm) => {
+            put(buf, PROXY_COMPRESSION_SELECTED, algorithm)
+        }
+        SocketPacket::ProxyCompressedData(packet) => {
+            buf.put_u8(PROXY_COMPRESSED_DATA);
+            buf.put_u16_le(packet.client_id);
+            buf.put_slice(&packet.packet.data);
+            Ok(())
+        }
+        SocketPacket::ProxyConnectionsRequest(count) => put(buf, PROXY_CONNECTIONS_REQUEST, count),
+        SocketPacket::ProxyConnectionsGranted(count) => put(buf, PROXY_CONNECTIONS_GRANTED, count),
+        SocketPacket::ProxyAttach => put(buf, PROXY_ATTACH, &()),
+        SocketPacket::ProxyPlayerLimitRequest(limit) => put(buf, PROXY_PLAYER_LIMIT_REQUEST, limit),
+        SocketPacket::ProxyPlayerLimitGranted(limit) => put(buf, PROXY_PLAYER_LIMIT_GRANTED, limit),
+        SocketPacket::MCHello(_) | SocketPacket::MCData(_) | SocketPacket::Unknown => {
+            Err(PacketError::EncodingError)
+        }
+    };
+    let length = result.and_then(|_| {
+        u16::try_from(buf.len() - start - size_of::<u16>()).map_err(|_| PacketError::EncodingError)
+    });
+    match length {
+        Ok(length) => {
+            buf[start..start + size_of::<u16>()].copy_from_slice(&length.to_be_bytes());
+            Ok(())
+        }
+        Err(e) => {
+            buf.truncate(start);
+            Err(e)
+        }
+    }
+}
+
+/// decodes the tag and the payload of a frame, returns `Unknown` for tags this version
+/// does not know. The data of `ProxyData` packets shares the memory of the frame.
+pub fn decode_packet(mut frame: Bytes) -> Result<SocketPacket, PacketError> {
+    if frame.is_empty() {
+        return Err(PacketError::NotValid);
+    }
+    let tag = frame.get_u8();
+    let payload = &frame[..];
+    Ok(match tag {
+        PROXY_HELLO => SocketPacket::ProxyHello(deserialize(payload)?),
+        PROXY_AUTH_REQUEST => {
+            SocketPacket::ProxyAuthRequest(payload.try_into().map_err(|_| PacketError::NotValid)?)
+        }
+        PROXY_AUTH_RESPONSE => {
+            SocketPacket::ProxyAuthResponse(payload.try_into().map_err(|_| PacketError::NotValid)?)
+        }
+        PROXY_HELLO_RESPONSE => SocketPacket::ProxyHelloResponse(deserialize(payload)?),
+        PROXY_JOIN => SocketPacket::ProxyJoin(deserialize(payload)?),
+        PROXY_DISCONNECT => SocketPacket::ProxyDisconnect(deserialize(payload)?),
+        PROXY_DISCONNECT_ACK => SocketPacket::ProxyDisconnectAck(deserialize(payload)?),
+        PROXY_ERROR => SocketPacket::ProxyError(deserialize(payload)?),
+        PROXY_DATA => {
+            if frame.len() < size_of::<u16>() + size_of::<u64>() {
+                return Err(PacketError::NotValid);
+            }
+            let client_id = frame.get_u16_le();
+            let length = frame.get_u64_le();
+            if length > frame.len() as u64 {
+                return Err(PacketError::NotValid);
+            }
+            let data = frame.split_to(length as usize);
+            SocketPacket::ProxyData(ProxyDataPacket::new(
+                MinecraftDataPacket { data },
+                client_id,
+            ))
+        }
+        PROXY_PING => SocketPacket::ProxyPing(deserialize(payload)?),
+        PROXY_PONG => SocketPacket::ProxyPong(deserialize(payload)?),
+        PROXY_DOMAIN_REQUEST => SocketPacket::ProxyDomainRequest(deserialize(payload)?),
+        PROXY_DOMAIN_VERIFIED => SocketPacket::ProxyDomainVerified(deserialize(payload)?),
+        PROXY_KEY_ROTATION => SocketPacket::ProxyKeyRotation(deserialize(payload)?),
+        PROXY_KEY_ROTATED => SocketPacket::ProxyKeyRotated(deserialize(payload)?),
+        PROXY_DRAINING => SocketPacket::ProxyDraining(deserialize(payload)?),
+        PROXY_PLAYER_FILTER => SocketPacket::ProxyPlayerFilter(deserialize(payload)?),
+        PROXY_COMPRESSION_OFFER => SocketPacket::ProxyCompressionOffer(deserialize(payload)?),
+        PROXY_COMPRESSION_SELECTED => SocketPacket::ProxyCompressionSelected(deserialize(payload)?),
+        PROXY_COMPRESSED_DATA => {
+            if frame.len() < size_of::<u16>() {
+                return Err(PacketError::NotValid);
+            }
+            let client_id = frame.get_u16_le();
+            let data = MinecraftDataPacket { data: frame };
+            SocketPacket::ProxyCompressedData(ProxyDataPacket::new(data, client_id))
+        }
+        PROXY_CONNECTIONS_REQUEST => SocketPacket::ProxyConnectionsRequest(deserialize(payload)?),
+        PROXY_CONNECTIONS_GRANTED => SocketPacket::ProxyConnectionsGranted(deserialize(payload)?),
+        PROXY_ATTACH => SocketPacket::ProxyAttach,
+        PROXY_PLAYER_LIMIT_REQUEST => SocketPacket::ProxyPlayerLimitRequest(deserialize(payload)?),
+        PROXY_PLAYER_LIMIT_GRANTED => SocketPacket::ProxyPlayerLimitGranted(deserialize(payload)?),
+        _ => SocketPacket::Unknown,
+    })
+}